@@ -0,0 +1,13 @@
+use bevy::prelude::*;
+
+// Per-character switches for the optional movement abilities. The systems implementing an ability
+// check its flag before letting a character use it, so the same movement code can be shared by
+// characters that are not supposed to have every move.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct MovementAbilities {
+    pub wall_jump: bool,
+    pub ledge_grab: bool,
+    // One extra jump in the air.
+    pub double_jump: bool,
+    pub dash: bool,
+}
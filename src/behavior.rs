@@ -0,0 +1,587 @@
+use std::collections::HashMap;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_egui::{EguiContextPass, EguiContexts, egui};
+use bevy_tnua::TnuaAnimatingState;
+use bevy_tnua::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::enemy::{EnemyBrain, EnemyThinkSystems, blocked_ahead};
+use crate::health::Health;
+use crate::melee::{AttackInput, AttackState};
+use crate::perception::PerceptionMemory;
+use crate::ron_asset::RonAssetLoader;
+use crate::{AnimationState, Facing, GameState};
+
+pub struct BehaviorPlugin;
+
+impl Plugin for BehaviorPlugin {
+    fn build(&self, app: &mut App) {
+        let mut custom_leaves = CustomLeaves::default();
+        custom_leaves.register("back_off", back_off);
+
+        app.init_asset::<BehaviorTree>()
+            .register_asset_loader(RonAssetLoader::<BehaviorTree>::new(&["bt.ron"]))
+            .insert_resource(custom_leaves)
+            .init_resource::<BehaviorDebugger>()
+            .add_systems(
+                FixedUpdate,
+                run_behavior_trees
+                    // The trees use what the enemies perceived, and feed the same controls as the
+                    // enemy state machine.
+                    .after(EnemyThinkSystems)
+                    .before(TnuaUserControlsSystemSet)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(Update, toggle_behavior_debugger)
+            .add_systems(EguiContextPass, show_behavior_debugger);
+    }
+}
+
+// A behavior tree, loaded from a `.bt.ron` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct BehaviorTree {
+    pub root: BehaviorNode,
+}
+
+#[derive(Deserialize, Debug)]
+pub enum BehaviorNode {
+    // Runs the children in order until one of them fails.
+    Sequence(Vec<BehaviorNode>),
+    // Runs the children in order until one of them succeeds.
+    Selector(Vec<BehaviorNode>),
+    // Runs all the children every tick, until they finish.
+    Parallel {
+        policy: ParallelPolicy,
+        children: Vec<BehaviorNode>,
+    },
+    Decorator {
+        decorator: Decorator,
+        child: Box<BehaviorNode>,
+    },
+    Leaf(Leaf),
+}
+
+impl BehaviorNode {
+    // How many nodes there are in this subtree. Nodes are numbered in pre-order, so the nodes of a
+    // subtree are `id..id + size`.
+    pub fn size(&self) -> usize {
+        1 + self.children().map(BehaviorNode::size).sum::<usize>()
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = &BehaviorNode> {
+        let children: &[BehaviorNode] = match self {
+            BehaviorNode::Sequence(children)
+            | BehaviorNode::Selector(children)
+            | BehaviorNode::Parallel { children, .. } => children,
+            BehaviorNode::Decorator { child, .. } => std::slice::from_ref(child.as_ref()),
+            BehaviorNode::Leaf(_) => &[],
+        };
+        children.iter()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParallelPolicy {
+    // Succeeds when all children succeed, fails as soon as one fails.
+    RequireAll,
+    // Succeeds as soon as one child succeeds, fails when all fail.
+    RequireOne,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum Decorator {
+    Invert,
+    // Turns failures into successes.
+    Succeed,
+    // Fails without running the child if it finished less than this many seconds ago.
+    Cooldown(f32),
+    // Fails if the child is still running after this many seconds.
+    Timeout(f32),
+}
+
+#[derive(Deserialize, Debug)]
+pub enum Leaf {
+    // Conditions.
+    SeesPlayer,
+    // Whether the player was last seen within this horizontal distance.
+    PlayerInRange(f32),
+    // Whether the health is below this fraction of the maximum.
+    HealthBelow(f32),
+    // Succeeds with this probability.
+    Chance(f32),
+
+    // Actions.
+    MoveToPlayer { speed: f32, distance: f32 },
+    MoveHome(f32),
+    Wait(f32),
+    Attack(AttackInput),
+    // Jumps this high.
+    Jump(f32),
+    FacePlayer,
+    Animate(AnimationState),
+    // A leaf registered in `CustomLeaves`.
+    Custom(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeStatus {
+    Success,
+    Failure,
+    Running,
+}
+
+// What a node remembers between ticks while it runs. Reset whenever the node finishes.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct NodeState {
+    // The child a sequence or a selector is at.
+    pub child: usize,
+    // Seconds since the node started running.
+    pub elapsed: f32,
+    // One bit per child of a parallel (up to 64) that finished.
+    pub finished: u64,
+}
+
+// Runs a behavior tree for an enemy, in place of the enemy state machine.
+#[derive(Component, Debug)]
+pub struct BehaviorRunner {
+    pub tree: Handle<BehaviorTree>,
+    states: Vec<NodeState>,
+    // Kept apart from `states` because cooldowns outlive the runs of their nodes.
+    cooldowns: Vec<f32>,
+    // What each node returned in the latest tick (`None` for nodes that didn't run), for the
+    // debugger.
+    pub statuses: Vec<Option<NodeStatus>>,
+    // A paused tree doesn't run, and the enemy stands still (e.g. during a cutscene).
+    pub paused: bool,
+}
+
+impl BehaviorRunner {
+    pub fn new(tree: Handle<BehaviorTree>) -> Self {
+        Self {
+            tree,
+            states: Vec::new(),
+            cooldowns: Vec::new(),
+            statuses: Vec::new(),
+            paused: false,
+        }
+    }
+
+    // Switches to another tree, which starts from its root.
+    pub fn set_tree(&mut self, tree: Handle<BehaviorTree>) {
+        *self = Self {
+            paused: self.paused,
+            ..Self::new(tree)
+        };
+    }
+}
+
+// Everything a leaf can look at and act upon.
+pub struct LeafContext<'a> {
+    pub dt: f32,
+    pub position: Vec3,
+    pub brain: &'a mut EnemyBrain,
+    pub memory: &'a PerceptionMemory,
+    pub attack_state: &'a mut AttackState,
+    pub facing: &'a mut Facing,
+    pub health: Option<&'a Health>,
+    pub animating_state: Option<&'a mut TnuaAnimatingState<AnimationState>>,
+    // Whether walking in a direction would bump into a wall or walk off a ledge.
+    pub blocked: &'a dyn Fn(f32) -> bool,
+}
+
+pub type LeafFn = fn(&mut LeafContext, &mut NodeState) -> NodeStatus;
+
+// Game-specific leaves, referred to by name from the trees with `Custom`.
+#[derive(Resource, Default)]
+pub struct CustomLeaves(HashMap<String, LeafFn>);
+
+impl CustomLeaves {
+    pub fn register(&mut self, name: &str, leaf: LeafFn) {
+        self.0.insert(name.to_string(), leaf);
+    }
+}
+
+// Toggled with F1.
+#[derive(Resource, Default)]
+pub struct BehaviorDebugger {
+    pub open: bool,
+}
+
+// For how long the jump button is "held" by the `Jump` leaf.
+const JUMP_HOLD: f32 = 0.25;
+
+fn run_behavior_trees(
+    time: Res<Time>,
+    trees: Res<Assets<BehaviorTree>>,
+    custom_leaves: Res<CustomLeaves>,
+    spatial_query: SpatialQuery,
+    characters_query: Query<(), With<Health>>,
+    mut query: Query<(
+        Entity,
+        &GlobalTransform,
+        &mut BehaviorRunner,
+        &mut EnemyBrain,
+        &PerceptionMemory,
+        &mut AttackState,
+        &mut Facing,
+        Option<&Health>,
+        Option<&mut TnuaAnimatingState<AnimationState>>,
+    )>,
+) {
+    let is_level = |entity: Entity| !characters_query.contains(entity);
+    for (
+        entity,
+        transform,
+        mut runner,
+        mut brain,
+        memory,
+        mut attack_state,
+        mut facing,
+        health,
+        animating_state,
+    ) in query.iter_mut()
+    {
+        let Some(tree) = trees.get(&runner.tree) else {
+            continue;
+        };
+        let size = tree.root.size();
+        if runner.states.len() != size {
+            runner.states = vec![NodeState::default(); size];
+            runner.cooldowns = vec![0.0; size];
+        }
+        let dt = time.delta_secs();
+        for cooldown in runner.cooldowns.iter_mut() {
+            *cooldown -= dt;
+        }
+        runner.statuses = vec![None; size];
+
+        // Only the leaves that are running this tick drive the enemy.
+        brain.heading = 0.0;
+        brain.jump = None;
+        if runner.paused {
+            continue;
+        }
+        let position = transform.translation();
+        let float_height = brain.float_height;
+        let blocked = |heading: f32| {
+            blocked_ahead(
+                &spatial_query,
+                entity,
+                position,
+                heading,
+                float_height,
+                &is_level,
+            )
+        };
+        let mut ctx = LeafContext {
+            dt,
+            position,
+            brain: &mut brain,
+            memory,
+            attack_state: &mut attack_state,
+            facing: &mut facing,
+            health,
+            animating_state: animating_state.map(Mut::into_inner),
+            blocked: &blocked,
+        };
+        tick(&tree.root, 0, &mut runner, &mut ctx, &custom_leaves);
+    }
+}
+
+fn tick(
+    node: &BehaviorNode,
+    id: usize,
+    runner: &mut BehaviorRunner,
+    ctx: &mut LeafContext,
+    custom_leaves: &CustomLeaves,
+) -> NodeStatus {
+    let status = match node {
+        BehaviorNode::Sequence(children) | BehaviorNode::Selector(children) => {
+            // A sequence goes on while its children succeed, a selector while they fail.
+            let go_on = if matches!(node, BehaviorNode::Sequence(_)) {
+                NodeStatus::Success
+            } else {
+                NodeStatus::Failure
+            };
+            let start = runner.states[id].child;
+            let mut child_id = id
+                + 1
+                + children[..start]
+                    .iter()
+                    .map(BehaviorNode::size)
+                    .sum::<usize>();
+            let mut status = go_on;
+            for (index, child) in children.iter().enumerate().skip(start) {
+                status = tick(child, child_id, runner, ctx, custom_leaves);
+                if status != go_on {
+                    runner.states[id].child = index;
+                    break;
+                }
+                child_id += child.size();
+            }
+            status
+        }
+        BehaviorNode::Parallel { policy, children } => {
+            // Children that finished aren't ticked again until the parallel itself finishes (or a
+            // `Jump` would jump again). Only the results that don't end the parallel are kept:
+            // successes when all children must succeed, failures when one is enough.
+            let kept = match policy {
+                ParallelPolicy::RequireAll => NodeStatus::Success,
+                ParallelPolicy::RequireOne => NodeStatus::Failure,
+            };
+            let mut finished = runner.states[id].finished;
+            let mut child_id = id + 1;
+            let (mut successes, mut failures) = (0, 0);
+            for (index, child) in children.iter().enumerate() {
+                let bit = 1u64.checked_shl(index as u32).unwrap_or(0);
+                let status = if finished & bit != 0 {
+                    kept
+                } else {
+                    tick(child, child_id, runner, ctx, custom_leaves)
+                };
+                match status {
+                    NodeStatus::Success => successes += 1,
+                    NodeStatus::Failure => failures += 1,
+                    NodeStatus::Running => {}
+                }
+                if status != NodeStatus::Running {
+                    finished |= bit;
+                }
+                child_id += child.size();
+            }
+            runner.states[id].finished = finished;
+            match policy {
+                ParallelPolicy::RequireAll if 0 < failures => NodeStatus::Failure,
+                ParallelPolicy::RequireAll if successes == children.len() => NodeStatus::Success,
+                ParallelPolicy::RequireOne if 0 < successes => NodeStatus::Success,
+                ParallelPolicy::RequireOne if failures == children.len() => NodeStatus::Failure,
+                _ => NodeStatus::Running,
+            }
+        }
+        BehaviorNode::Decorator { decorator, child } => match *decorator {
+            Decorator::Invert => match tick(child, id + 1, runner, ctx, custom_leaves) {
+                NodeStatus::Success => NodeStatus::Failure,
+                NodeStatus::Failure => NodeStatus::Success,
+                NodeStatus::Running => NodeStatus::Running,
+            },
+            Decorator::Succeed => match tick(child, id + 1, runner, ctx, custom_leaves) {
+                NodeStatus::Running => NodeStatus::Running,
+                _ => NodeStatus::Success,
+            },
+            Decorator::Cooldown(_) if 0.0 < runner.cooldowns[id] => NodeStatus::Failure,
+            Decorator::Cooldown(seconds) => {
+                let status = tick(child, id + 1, runner, ctx, custom_leaves);
+                if status != NodeStatus::Running {
+                    runner.cooldowns[id] = seconds;
+                }
+                status
+            }
+            Decorator::Timeout(seconds) => {
+                runner.states[id].elapsed += ctx.dt;
+                if seconds < runner.states[id].elapsed {
+                    NodeStatus::Failure
+                } else {
+                    tick(child, id + 1, runner, ctx, custom_leaves)
+                }
+            }
+        },
+        BehaviorNode::Leaf(leaf) => {
+            let mut state = runner.states[id];
+            let status = tick_leaf(leaf, ctx, &mut state, custom_leaves);
+            state.elapsed += ctx.dt;
+            runner.states[id] = state;
+            status
+        }
+    };
+
+    runner.statuses[id] = Some(status);
+    // Finished nodes start over the next time they run - and so do their descendants, which may
+    // have been cut short (e.g. by a timeout).
+    if status != NodeStatus::Running {
+        runner.states[id..id + node.size()].fill(NodeState::default());
+    }
+    status
+}
+
+fn tick_leaf(
+    leaf: &Leaf,
+    ctx: &mut LeafContext,
+    state: &mut NodeState,
+    custom_leaves: &CustomLeaves,
+) -> NodeStatus {
+    let succeed_if = |condition: bool| {
+        if condition {
+            NodeStatus::Success
+        } else {
+            NodeStatus::Failure
+        }
+    };
+    let player_offset = ctx
+        .memory
+        .last_known
+        .map(|target| target.x - ctx.position.x);
+
+    match leaf {
+        Leaf::SeesPlayer => succeed_if(ctx.memory.sees_player),
+        Leaf::PlayerInRange(range) => {
+            succeed_if(player_offset.is_some_and(|offset| offset.abs() <= *range))
+        }
+        Leaf::HealthBelow(fraction) => succeed_if(
+            ctx.health
+                .is_some_and(|health| health.current < health.max * fraction),
+        ),
+        Leaf::Chance(probability) => {
+            succeed_if(rand::thread_rng().gen_bool(probability.clamp(0.0, 1.0) as f64))
+        }
+        Leaf::MoveToPlayer { speed, distance } => {
+            let Some(offset) = player_offset else {
+                return NodeStatus::Failure;
+            };
+            walk_towards(ctx, offset, *speed, *distance)
+        }
+        Leaf::MoveHome(speed) => {
+            let offset = ctx.brain.home.x - ctx.position.x;
+            walk_towards(ctx, offset, *speed, 0.5)
+        }
+        Leaf::Wait(seconds) => {
+            if *seconds <= state.elapsed {
+                NodeStatus::Success
+            } else {
+                NodeStatus::Running
+            }
+        }
+        Leaf::Attack(input) => {
+            if state.elapsed == 0.0 {
+                ctx.attack_state.request(*input);
+                return NodeStatus::Running;
+            }
+            if ctx.attack_state.active.is_some() || ctx.attack_state.buffer.is_some() {
+                NodeStatus::Running
+            } else {
+                NodeStatus::Success
+            }
+        }
+        Leaf::Jump(height) => {
+            if state.elapsed < JUMP_HOLD {
+                ctx.brain.jump = Some(*height);
+                NodeStatus::Running
+            } else {
+                NodeStatus::Success
+            }
+        }
+        Leaf::FacePlayer => match player_offset.filter(|offset| *offset != 0.0) {
+            Some(offset) => {
+                ctx.facing.0 = offset.signum();
+                NodeStatus::Success
+            }
+            None => NodeStatus::Failure,
+        },
+        Leaf::Animate(animation) => match ctx.animating_state.as_mut() {
+            Some(animating_state) => {
+                animating_state.update_by_discriminant(animation.clone());
+                NodeStatus::Success
+            }
+            None => NodeStatus::Failure,
+        },
+        Leaf::Custom(name) => match custom_leaves.0.get(name) {
+            Some(leaf) => leaf(ctx, state),
+            None => {
+                warn!("Unknown behavior tree leaf {name}");
+                NodeStatus::Failure
+            }
+        },
+    }
+}
+
+fn walk_towards(ctx: &mut LeafContext, offset: f32, speed: f32, distance: f32) -> NodeStatus {
+    if offset.abs() <= distance {
+        return NodeStatus::Success;
+    }
+    let heading = offset.signum();
+    if (ctx.blocked)(heading) {
+        return NodeStatus::Failure;
+    }
+    ctx.brain.heading = heading;
+    ctx.brain.speed = speed;
+    NodeStatus::Running
+}
+
+// Steps away from the player for half a second.
+fn back_off(ctx: &mut LeafContext, state: &mut NodeState) -> NodeStatus {
+    let Some(target) = ctx.memory.last_known else {
+        return NodeStatus::Failure;
+    };
+    if 0.5 <= state.elapsed {
+        return NodeStatus::Success;
+    }
+    let heading = -(target.x - ctx.position.x).signum();
+    if (ctx.blocked)(heading) {
+        return NodeStatus::Failure;
+    }
+    ctx.brain.heading = heading;
+    ctx.brain.speed = ctx.brain.ai.patrol_speed;
+    NodeStatus::Running
+}
+
+fn toggle_behavior_debugger(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut debugger: ResMut<BehaviorDebugger>,
+) {
+    if keyboard.just_pressed(KeyCode::F1) {
+        debugger.open = !debugger.open;
+    }
+}
+
+// Shows the trees of all the enemies that have one, with the nodes that ran in the latest tick
+// colored by what they returned.
+fn show_behavior_debugger(
+    mut contexts: EguiContexts,
+    mut debugger: ResMut<BehaviorDebugger>,
+    trees: Res<Assets<BehaviorTree>>,
+    query: Query<(Entity, &BehaviorRunner)>,
+) {
+    if !debugger.open {
+        return;
+    }
+    egui::Window::new("Behavior trees")
+        .open(&mut debugger.open)
+        .show(contexts.ctx_mut(), |ui| {
+            for (entity, runner) in query.iter() {
+                let Some(tree) = trees.get(&runner.tree) else {
+                    continue;
+                };
+                egui::CollapsingHeader::new(format!("{entity}"))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        show_node(ui, &tree.root, 0, &runner.statuses);
+                    });
+            }
+        });
+}
+
+fn show_node(ui: &mut egui::Ui, node: &BehaviorNode, id: usize, statuses: &[Option<NodeStatus>]) {
+    let label = match node {
+        BehaviorNode::Sequence(_) => "Sequence".to_string(),
+        BehaviorNode::Selector(_) => "Selector".to_string(),
+        BehaviorNode::Parallel { policy, .. } => format!("Parallel ({policy:?})"),
+        BehaviorNode::Decorator { decorator, .. } => format!("{decorator:?}"),
+        BehaviorNode::Leaf(leaf) => format!("{leaf:?}"),
+    };
+    let color = match statuses.get(id).copied().flatten() {
+        Some(NodeStatus::Running) => egui::Color32::YELLOW,
+        Some(NodeStatus::Success) => egui::Color32::GREEN,
+        Some(NodeStatus::Failure) => egui::Color32::RED,
+        None => egui::Color32::GRAY,
+    };
+    ui.label(egui::RichText::new(label).color(color));
+    ui.indent(id, |ui| {
+        let mut child_id = id + 1;
+        for child in node.children() {
+            show_node(ui, child, child_id, statuses);
+            child_id += child.size();
+        }
+    });
+}
@@ -0,0 +1,370 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::GameState;
+use crate::behavior::BehaviorRunner;
+use crate::health::{
+    DeathEvent, DeathSequence, Health, HealthSystems, Invulnerability, Loot, LootDropEvent,
+};
+use crate::juice::CameraShake;
+use crate::melee::{MeleeAttack, MeleeAttacker};
+use crate::perception::PerceptionMemory;
+use crate::ron_asset::RonAssetLoader;
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BossDef>()
+            .register_asset_loader(RonAssetLoader::<BossDef>::new(&["boss.ron"]))
+            .add_event::<BossPhaseEvent>()
+            .add_event::<BossDefeatEvent>()
+            .add_event::<BossDefeatedEvent>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    start_defeat_sequences,
+                    advance_boss_phases,
+                    play_defeat_sequences,
+                )
+                    .chain()
+                    .after(HealthSystems)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(
+                Update,
+                (spawn_boss_health_bars, update_boss_health_bars)
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// Describes a boss fight, loaded from a `.boss.ron` file. The boss itself is an enemy with a
+// behavior tree - this only adds the phases and the defeat on top.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct BossDef {
+    // Shown over the health bar.
+    pub name: String,
+    // In order. The first phase starts when the boss notices the player.
+    pub phases: Vec<BossPhaseDef>,
+    pub defeat: BossDefeatDef,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BossPhaseDef {
+    pub name: String,
+    // The phase starts once the boss's health drops to this fraction of its max.
+    pub health_below: f32,
+    // The path of the `.bt.ron` behavior tree the boss switches to, if any.
+    #[serde(default)]
+    pub behavior: Option<String>,
+    // Replaces the boss's melee attack, if set.
+    #[serde(default)]
+    pub attack: Option<MeleeAttack>,
+    // Passed on with the `BossPhaseEvent`, for whatever plays cutscenes.
+    #[serde(default)]
+    pub cutscene: Option<String>,
+    // Seconds the boss holds still (and takes no damage) as the phase begins.
+    #[serde(default)]
+    pub transition: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BossDefeatDef {
+    // Seconds between the killing blow and the boss disappearing.
+    pub duration: f32,
+    // Camera trauma added per second while the boss goes down.
+    pub trauma: f32,
+    #[serde(default)]
+    pub cutscene: Option<String>,
+}
+
+#[derive(Component, Debug)]
+pub struct Boss {
+    pub def: Handle<BossDef>,
+}
+
+impl Boss {
+    // Spawn this along with an `Enemy` whose definition has a behavior tree.
+    pub fn bundle(def: Handle<BossDef>) -> impl Bundle {
+        (
+            Boss { def },
+            BossState::default(),
+            // Used to shield the boss during phase transitions.
+            Invulnerability::new(0.0),
+            DeathSequence,
+        )
+    }
+}
+
+#[derive(Component, Default, Debug)]
+pub struct BossState {
+    // `None` until the fight starts.
+    pub phase: Option<usize>,
+    // Seconds left in the transition into the current phase.
+    pub transition: Option<f32>,
+    // Seconds left in the defeat sequence, once the boss is dead.
+    pub defeat: Option<f32>,
+}
+
+// Sent when a boss enters a phase (including the first one, when the fight starts).
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "the template has no cutscene player")]
+pub struct BossPhaseEvent {
+    pub boss: Entity,
+    pub phase: usize,
+    pub cutscene: Option<String>,
+}
+
+// Sent when a boss dies and its defeat sequence starts.
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "the template has no cutscene player")]
+pub struct BossDefeatEvent {
+    pub boss: Entity,
+    pub cutscene: Option<String>,
+}
+
+// Sent when the defeat sequence is over and the boss is gone.
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "nothing follows up on a defeat yet")]
+pub struct BossDefeatedEvent {
+    pub boss: Entity,
+}
+
+fn start_defeat_sequences(
+    mut death_reader: EventReader<DeathEvent>,
+    defs: Res<Assets<BossDef>>,
+    mut query: Query<(&Boss, &mut BossState, &mut BehaviorRunner)>,
+    mut defeat_writer: EventWriter<BossDefeatEvent>,
+) {
+    for event in death_reader.read() {
+        let Ok((boss, mut state, mut runner)) = query.get_mut(event.entity) else {
+            continue;
+        };
+        let def = defs.get(&boss.def);
+        state.defeat = Some(def.map_or(0.0, |def| def.defeat.duration));
+        state.transition = None;
+        runner.paused = true;
+        defeat_writer.write(BossDefeatEvent {
+            boss: event.entity,
+            cutscene: def.and_then(|def| def.defeat.cutscene.clone()),
+        });
+    }
+}
+
+fn advance_boss_phases(
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    defs: Res<Assets<BossDef>>,
+    mut query: Query<(
+        Entity,
+        &Boss,
+        &mut BossState,
+        &Health,
+        &PerceptionMemory,
+        &mut BehaviorRunner,
+        &mut MeleeAttacker,
+        &mut Invulnerability,
+    )>,
+    mut phase_writer: EventWriter<BossPhaseEvent>,
+) {
+    for (entity, boss, mut state, health, memory, mut runner, mut attacker, mut invulnerability) in
+        query.iter_mut()
+    {
+        let Some(def) = defs.get(&boss.def) else {
+            continue;
+        };
+        if state.defeat.is_some() {
+            continue;
+        }
+        if let Some(remaining) = state.transition {
+            let remaining = remaining - time.delta_secs();
+            state.transition = (0.0 < remaining).then_some(remaining);
+            runner.paused = state.transition.is_some();
+        }
+
+        // The fight starts when the boss notices the player. A big enough hit skips phases, but
+        // healing never goes back to an earlier one.
+        if state.phase.is_none() && !memory.is_aware() {
+            continue;
+        }
+        let fraction = health.current / health.max;
+        let Some(phase) = def
+            .phases
+            .iter()
+            .rposition(|phase| fraction <= phase.health_below)
+        else {
+            continue;
+        };
+        if state.phase.is_some_and(|current| phase <= current) {
+            continue;
+        }
+
+        let phase_def = &def.phases[phase];
+        state.phase = Some(phase);
+        if let Some(behavior) = &phase_def.behavior {
+            runner.set_tree(asset_server.load(behavior.clone()));
+        }
+        if let Some(attack) = &phase_def.attack {
+            attacker.attack = attack.clone();
+        }
+        if 0.0 < phase_def.transition {
+            state.transition = Some(phase_def.transition);
+            runner.paused = true;
+            invulnerability.remaining = phase_def.transition;
+        }
+        phase_writer.write(BossPhaseEvent {
+            boss: entity,
+            phase,
+            cutscene: phase_def.cutscene.clone(),
+        });
+    }
+}
+
+// The boss shakes the screen and shrinks away, then leaves its loot behind.
+fn play_defeat_sequences(
+    time: Res<Time>,
+    mut commands: Commands,
+    defs: Res<Assets<BossDef>>,
+    mut query: Query<(
+        Entity,
+        &Boss,
+        &mut BossState,
+        &mut Transform,
+        &GlobalTransform,
+        Option<&Loot>,
+    )>,
+    mut cameras_query: Query<&mut CameraShake>,
+    mut loot_writer: EventWriter<LootDropEvent>,
+    mut defeated_writer: EventWriter<BossDefeatedEvent>,
+) {
+    for (entity, boss, mut state, mut transform, global_transform, loot) in query.iter_mut() {
+        let Some(remaining) = state.defeat else {
+            continue;
+        };
+        let remaining = remaining - time.delta_secs();
+        state.defeat = Some(remaining);
+        let def = defs.get(&boss.def);
+
+        if 0.0 < remaining {
+            let duration = def.map_or(1.0, |def| def.defeat.duration);
+            transform.scale = Vec3::splat((remaining / duration).clamp(0.1, 1.0));
+            for mut shake in cameras_query.iter_mut() {
+                shake.add_trauma(def.map_or(0.0, |def| def.defeat.trauma) * time.delta_secs());
+            }
+            continue;
+        }
+
+        for drop in loot.into_iter().flat_map(|loot| loot.0.iter()) {
+            loot_writer.write(LootDropEvent {
+                position: global_transform.translation(),
+                item: drop.item.clone(),
+                count: drop.count,
+            });
+        }
+        defeated_writer.write(BossDefeatedEvent { boss: entity });
+        commands.entity(entity).despawn();
+    }
+}
+
+// The HUD bar of a boss, shown from the start of the fight until the boss is gone.
+#[derive(Component, Debug)]
+pub struct BossHealthBar {
+    pub boss: Entity,
+    label: Entity,
+    fill: Entity,
+}
+
+fn spawn_boss_health_bars(
+    mut commands: Commands,
+    defs: Res<Assets<BossDef>>,
+    bosses_query: Query<(Entity, &Boss, &BossState)>,
+    bars_query: Query<&BossHealthBar>,
+) {
+    for (entity, boss, state) in bosses_query.iter() {
+        if state.phase.is_none() || bars_query.iter().any(|bar| bar.boss == entity) {
+            continue;
+        }
+        let Some(def) = defs.get(&boss.def) else {
+            continue;
+        };
+        let label = commands
+            .spawn((
+                Text::new(def.name.clone()),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+            ))
+            .id();
+        let fill = commands
+            .spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.8, 0.1, 0.1)),
+            ))
+            .id();
+        let frame = commands
+            .spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Px(14.0),
+                    ..default()
+                },
+                BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            ))
+            .add_child(fill)
+            .id();
+        commands
+            .spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(20.0),
+                    left: Val::Percent(20.0),
+                    width: Val::Percent(60.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(4.0),
+                    ..default()
+                },
+                BossHealthBar {
+                    boss: entity,
+                    label,
+                    fill,
+                },
+            ))
+            .add_children(&[label, frame]);
+    }
+}
+
+fn update_boss_health_bars(
+    mut commands: Commands,
+    defs: Res<Assets<BossDef>>,
+    bars_query: Query<(Entity, &BossHealthBar)>,
+    bosses_query: Query<(&Boss, &BossState, &Health)>,
+    mut nodes_query: Query<&mut Node>,
+    mut texts_query: Query<&mut Text>,
+) {
+    for (bar_entity, bar) in bars_query.iter() {
+        let Ok((boss, state, health)) = bosses_query.get(bar.boss) else {
+            commands.entity(bar_entity).despawn();
+            continue;
+        };
+        if let Ok(mut node) = nodes_query.get_mut(bar.fill) {
+            node.width = Val::Percent((health.current / health.max).clamp(0.0, 1.0) * 100.0);
+        }
+        // The label names the phase too, e.g. "The Warden - Enraged".
+        let phase = defs
+            .get(&boss.def)
+            .zip(state.phase)
+            .and_then(|(def, phase)| Some((def, def.phases.get(phase)?)));
+        if let (Some((def, phase)), Ok(mut text)) = (phase, texts_query.get_mut(bar.label)) {
+            let label = format!("{} - {}", def.name, phase.name);
+            if text.0 != label {
+                text.0 = label;
+            }
+        }
+    }
+}
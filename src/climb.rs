@@ -0,0 +1,151 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+
+use crate::controls::KeyPresses;
+use crate::layers::GameLayer;
+use crate::{FLOAT_HEIGHT, GameState, Player};
+
+pub struct ClimbPlugin;
+
+impl Plugin for ClimbPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            update_climbing
+                .before(TnuaUserControlsSystemSet)
+                .run_if(in_state(GameState::Ready)),
+        );
+    }
+}
+
+// Marks a sensor volume (a ladder, vines...) that characters can climb while inside it.
+#[derive(Component)]
+pub struct Climbable;
+
+impl Climbable {
+    // Everything a climbable zone needs besides its transform and collider.
+    pub fn bundle() -> impl Bundle {
+        (
+            Climbable,
+            Sensor,
+            CollisionLayers::new(GameLayer::Trigger, [GameLayer::Default]),
+        )
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct ClimbMovement {
+    pub climb_speed: f32,
+    // How strongly the character is pulled towards the middle of the climbable zone.
+    pub centering_strength: f32,
+    // The height of the little hop when jumping off a climbable.
+    pub jump_off_height: f32,
+}
+
+impl Default for ClimbMovement {
+    fn default() -> Self {
+        Self {
+            climb_speed: 4.0,
+            centering_strength: 10.0,
+            jump_off_height: 1.5,
+        }
+    }
+}
+
+#[derive(Component, Default, Debug)]
+pub struct ClimbState {
+    // The climbable the character is currently on. `None` means the character is not climbing.
+    pub climbing: Option<Entity>,
+}
+
+fn update_climbing(
+    gravity: Res<Gravity>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    presses: Res<KeyPresses>,
+    spatial_query: SpatialQuery,
+    climbables_query: Query<&ColliderAabb, With<Climbable>>,
+    mut query: Query<
+        (
+            Entity,
+            &Transform,
+            &Collider,
+            &TnuaController,
+            &ClimbMovement,
+            &mut ClimbState,
+            &mut LinearVelocity,
+            &mut GravityScale,
+        ),
+        With<Player>,
+    >,
+) {
+    let Ok((
+        entity,
+        transform,
+        collider,
+        controller,
+        climb,
+        mut state,
+        mut velocity,
+        mut gravity_scale,
+    )) = query.single_mut()
+    else {
+        return;
+    };
+
+    let mut input = 0.0;
+    if keyboard.pressed(KeyCode::ArrowUp) {
+        input += 1.0;
+    }
+    if keyboard.pressed(KeyCode::ArrowDown) {
+        input -= 1.0;
+    }
+
+    let filter = SpatialQueryFilter::from_excluded_entities([entity]).with_mask(GameLayer::Trigger);
+    let overlapping = spatial_query
+        .shape_intersections(collider, transform.translation, Quat::IDENTITY, &filter)
+        .into_iter()
+        .find_map(|climbable| Some((climbable, climbables_query.get(climbable).ok()?)));
+
+    let Some(climbable) = state.climbing else {
+        let Some((climbable, aabb)) = overlapping else {
+            return;
+        };
+        // Up always grabs the climbable, but Down should only do it if the climbable continues
+        // below the character's feet - otherwise it's just a crouch next to a ladder.
+        let feet = transform.translation.y - FLOAT_HEIGHT;
+        let grab = 0.0 < input || (input < 0.0 && aabb.min.y < feet - 0.1);
+        if grab {
+            state.climbing = Some(climbable);
+            gravity_scale.0 = 0.0;
+            velocity.0 = Vec3::ZERO;
+        }
+        return;
+    };
+
+    let standing = controller
+        .concrete_basis::<TnuaBuiltinWalk>()
+        .is_some_and(|(_, basis_state)| basis_state.standing_on_entity().is_some());
+    let still_on_it = overlapping.is_some_and(|(entity, _)| entity == climbable);
+    let jumped = presses.just_pressed(KeyCode::Space);
+
+    // Climbing ends when jumping off, when climbing past either end of the climbable, or when
+    // climbing down all the way to the ground.
+    if jumped || !still_on_it || (standing && input < 0.0) {
+        state.climbing = None;
+        gravity_scale.0 = 1.0;
+        if jumped {
+            velocity.y = (2.0 * gravity.0.length() * climb.jump_off_height).sqrt();
+        }
+        return;
+    }
+
+    let center_x = overlapping.map_or(transform.translation.x, |(_, aabb)| {
+        0.5 * (aabb.min.x + aabb.max.x)
+    });
+    velocity.0 = Vec3::new(
+        (center_x - transform.translation.x) * climb.centering_strength,
+        input * climb.climb_speed,
+        0.0,
+    );
+}
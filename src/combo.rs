@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+use serde::Deserialize;
+
+use crate::GameState;
+use crate::melee::{
+    ActiveAttack, AttackInput, AttackState, MeleeAttack, MeleeAttacker, MeleeSystems,
+};
+use crate::ron_asset::RonAssetLoader;
+
+pub struct ComboPlugin;
+
+impl Plugin for ComboPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ComboSet>()
+            .register_asset_loader(RonAssetLoader::<ComboSet>::new(&["combos.ron"]))
+            .add_systems(
+                FixedUpdate,
+                select_attacks
+                    .before(MeleeSystems)
+                    .after(TnuaUserControlsSystemSet)
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// All the moves a character can string together, loaded from a `.combos.ron` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct ComboSet {
+    pub moves: Vec<ComboMove>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ComboMove {
+    pub name: String,
+    pub input: AttackInput,
+    #[serde(default)]
+    pub condition: MoveCondition,
+    // The move this one chains from. Moves without it start a combo.
+    #[serde(default)]
+    pub follows: Option<String>,
+    // The id the move has to be unlocked with (see `UnlockedMoves`) before it can come out.
+    #[serde(default)]
+    pub requires: Option<String>,
+    pub attack: MeleeAttack,
+    // The time range (in seconds since the move started) in which it can be cancelled into the
+    // moves that follow it.
+    pub cancel_window: (f32, f32),
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MoveCondition {
+    #[default]
+    Grounded,
+    Airborne,
+    Any,
+}
+
+impl MoveCondition {
+    pub fn allows(self, airborne: bool) -> bool {
+        match self {
+            MoveCondition::Grounded => !airborne,
+            MoveCondition::Airborne => airborne,
+            MoveCondition::Any => true,
+        }
+    }
+}
+
+// The moves a character has unlocked, by their `requires` id. Characters without this component can
+// use every move of their combo set.
+#[derive(Component, Clone, Debug, Default)]
+pub struct UnlockedMoves(pub HashSet<String>);
+
+impl ComboSet {
+    // Picks the move `input` leads to. With an attack in progress, this is one of the moves that
+    // follow it - but only inside its cancel window. Otherwise it's a combo starter.
+    pub fn next_move(
+        &self,
+        active: Option<&ActiveAttack>,
+        input: AttackInput,
+        airborne: bool,
+        unlocked: Option<&UnlockedMoves>,
+    ) -> Option<&ComboMove> {
+        let follows = match active {
+            Some(active) => {
+                let current = self.find(active.name.as_deref()?)?;
+                let (start, end) = current.cancel_window;
+                if !(start <= active.total_elapsed && active.total_elapsed <= end) {
+                    return None;
+                }
+                Some(current.name.as_str())
+            }
+            None => None,
+        };
+        self.moves.iter().find(|combo_move| {
+            combo_move.follows.as_deref() == follows
+                && combo_move.input == input
+                && combo_move.condition.allows(airborne)
+                && combo_move.requires.as_ref().is_none_or(|requires| {
+                    unlocked.is_none_or(|unlocked| unlocked.0.contains(requires))
+                })
+        })
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ComboMove> {
+        self.moves.iter().find(|combo_move| combo_move.name == name)
+    }
+}
+
+fn select_attacks(
+    time: Res<Time>,
+    combo_sets: Res<Assets<ComboSet>>,
+    mut query: Query<(
+        &MeleeAttacker,
+        &mut AttackState,
+        Option<&TnuaController>,
+        Option<&UnlockedMoves>,
+    )>,
+) {
+    for (attacker, mut state, controller, unlocked) in query.iter_mut() {
+        let Some(buffered) = state.buffer.as_mut() else {
+            continue;
+        };
+        buffered.age += time.delta_secs();
+        if attacker.buffer_time < buffered.age {
+            state.buffer = None;
+            continue;
+        }
+        let input = buffered.input;
+
+        let airborne = controller
+            .and_then(|controller| controller.concrete_basis::<TnuaBuiltinWalk>())
+            .is_some_and(|(_, basis_state)| basis_state.standing_on_entity().is_none());
+        let combo_set = attacker
+            .combos
+            .as_ref()
+            .and_then(|handle| combo_sets.get(handle));
+
+        let next = match combo_set {
+            Some(combo_set) => combo_set
+                .next_move(state.active.as_ref(), input, airborne, unlocked)
+                .map(|combo_move| (Some(combo_move.name.clone()), combo_move.attack.clone())),
+            // Without a combo set only the basic attack is available.
+            None => (input == AttackInput::Light && state.active.is_none())
+                .then(|| (None, attacker.attack.clone())),
+        };
+        // If nothing can come out right now, the input stays in the buffer and is tried again
+        // next tick - until it gets too old.
+        if let Some((name, attack)) = next {
+            state.buffer = None;
+            state.start(name, attack);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::collections::HashSet;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KeyPresses>()
+            .add_systems(PreUpdate, latch_key_presses.after(InputSystem))
+            .add_systems(FixedLast, clear_key_presses);
+    }
+}
+
+// The keys pressed since the last fixed tick. FixedUpdate can run zero or several times in a frame,
+// so a `just_pressed` read there straight from `ButtonInput` would be missed or seen twice. Held
+// keys can still be read from `ButtonInput`.
+#[derive(Resource, Default, Debug)]
+pub struct KeyPresses {
+    pressed: HashSet<KeyCode>,
+}
+
+impl KeyPresses {
+    pub fn just_pressed(&self, key: KeyCode) -> bool {
+        self.pressed.contains(&key)
+    }
+}
+
+fn latch_key_presses(keyboard: Res<ButtonInput<KeyCode>>, mut presses: ResMut<KeyPresses>) {
+    presses.pressed.extend(keyboard.get_just_pressed().copied());
+}
+
+// The presses are only cleared after a fixed tick has seen them.
+fn clear_key_presses(mut presses: ResMut<KeyPresses>) {
+    presses.pressed.clear();
+}
@@ -0,0 +1,131 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::builtins::TnuaBuiltinCrouch;
+use bevy_tnua::control_helpers::TnuaCrouchEnforcerPlugin;
+use bevy_tnua::prelude::*;
+
+use crate::melee::Hurtbox;
+use crate::{GameState, Player, WALK_SPEED};
+
+pub struct CrouchPlugin;
+
+impl Plugin for CrouchPlugin {
+    fn build(&self, app: &mut App) {
+        // The crouch enforcer keeps feeding the crouch action while there is an obstacle above the
+        // character, so that it won't stand up into a low ceiling.
+        app.add_plugins(TnuaCrouchEnforcerPlugin::new(FixedUpdate))
+            .add_systems(
+                FixedUpdate,
+                (
+                    update_crouch.before(TnuaUserControlsSystemSet),
+                    resize_crouch_collider.after(TnuaUserControlsSystemSet),
+                )
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct CrouchMovement {
+    pub standing_collider: Collider,
+    pub crouching_collider: Collider,
+    // Added to the walk basis' `float_height` while crouching. Must keep the float height above the
+    // bottom of `crouching_collider`.
+    pub float_offset: f32,
+    // Scales the walk speed while crouching.
+    pub speed_multiplier: f32,
+    // Crouching while running at least this fast turns into a slide that keeps the momentum.
+    pub slide_min_speed: f32,
+    // How fast (in m/s²) a slide loses its speed.
+    pub slide_deceleration: f32,
+}
+
+impl Default for CrouchMovement {
+    fn default() -> Self {
+        Self {
+            standing_collider: Collider::capsule(0.5, 1.0),
+            crouching_collider: Collider::capsule(0.5, 0.2),
+            float_offset: -1.3,
+            speed_multiplier: 0.4,
+            slide_min_speed: 6.0,
+            slide_deceleration: 8.0,
+        }
+    }
+}
+
+#[derive(Component, Default, Debug)]
+pub struct CrouchState {
+    // Whether the character wants to crouch. It may still be crouching when this is off, if the
+    // crouch enforcer found a low ceiling above it.
+    pub crouching: bool,
+    // The velocity of the current slide, if the character is sliding.
+    pub slide: Option<Vec3>,
+    collider_crouched: bool,
+}
+
+fn update_crouch(
+    time: Res<Time>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut query: Query<(&TnuaController, &CrouchMovement, &mut CrouchState), With<Player>>,
+) {
+    let Ok((controller, crouch, mut state)) = query.single_mut() else {
+        return;
+    };
+
+    let wants_to_crouch = keyboard.pressed(KeyCode::ArrowDown);
+    let running_velocity = controller
+        .concrete_basis::<TnuaBuiltinWalk>()
+        .filter(|(_, basis_state)| basis_state.standing_on_entity().is_some())
+        .map(|(_, basis_state)| basis_state.running_velocity);
+
+    if wants_to_crouch && !state.crouching {
+        // Only start a slide at the moment the crouch begins - crouching first and then walking
+        // should not slide.
+        state.slide = running_velocity
+            .filter(|velocity| crouch.slide_min_speed <= velocity.length())
+            .map(|velocity| Vec3::new(velocity.x, 0.0, velocity.z));
+    }
+    state.crouching = wants_to_crouch;
+
+    if let Some(slide) = state.slide {
+        let speed = slide.length() - crouch.slide_deceleration * time.delta_secs();
+        // The slide ends once it's down to crouch-walking speed, when the player stands up or when
+        // the character leaves the ground.
+        state.slide = (wants_to_crouch
+            && running_velocity.is_some()
+            && crouch.speed_multiplier * WALK_SPEED < speed)
+            .then(|| slide.normalize_or_zero() * speed);
+    }
+}
+
+// The hurtboxes among the character's children shrink along with the body, so that crouching
+// actually ducks under attacks.
+fn resize_crouch_collider(
+    mut query: Query<(
+        &TnuaController,
+        &CrouchMovement,
+        &mut CrouchState,
+        &mut Collider,
+        Option<&Children>,
+    )>,
+    mut hurtboxes_query: Query<&mut Collider, (With<Hurtbox>, Without<CrouchMovement>)>,
+) {
+    for (controller, crouch, mut state, mut collider, children) in query.iter_mut() {
+        // Use the actual action rather than the input, so that the collider also stays small while
+        // the crouch enforcer keeps the character down.
+        let crouched = controller.action_name() == Some(TnuaBuiltinCrouch::NAME);
+        if crouched == state.collider_crouched {
+            continue;
+        }
+        *collider = if crouched {
+            crouch.crouching_collider.clone()
+        } else {
+            crouch.standing_collider.clone()
+        };
+        let mut hurtboxes = hurtboxes_query.iter_many_mut(children.into_iter().flatten());
+        while let Some(mut hurtbox) = hurtboxes.fetch_next() {
+            *hurtbox = collider.clone();
+        }
+        state.collider_crouched = crouched;
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::ron_asset::RonAssetLoader;
+
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<DamageTypeRegistry>()
+            .register_asset_loader(RonAssetLoader::<DamageTypeRegistry>::new(&["damage.ron"]))
+            .add_systems(Startup, load_damage_types);
+    }
+}
+
+// The damage type of anything that doesn't say otherwise.
+pub const PHYSICAL: &str = "physical";
+
+pub fn default_damage_type() -> String {
+    PHYSICAL.to_string()
+}
+
+// All the damage types of the game, loaded from a `.damage.ron` file. Damage types are referred to
+// by their `id` everywhere else.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct DamageTypeRegistry {
+    pub types: Vec<DamageTypeDef>,
+}
+
+impl DamageTypeRegistry {
+    pub fn get(&self, id: &str) -> Option<&DamageTypeDef> {
+        self.types.iter().find(|damage_type| damage_type.id == id)
+    }
+}
+
+#[derive(Resource)]
+pub struct DamageTypeRegistryHandle(pub Handle<DamageTypeRegistry>);
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DamageTypeDef {
+    pub id: String,
+    // Status effects that hits of this type may inflict (e.g. fire burns).
+    #[serde(default)]
+    pub inflicts: Vec<String>,
+    // The chance (0 to 1) of each of the `inflicts` effects to be inflicted by a hit.
+    #[serde(default)]
+    pub inflict_chance: f32,
+}
+
+// How much of each damage type an entity shrugs off. 0.5 halves the damage, 1 makes the entity
+// immune, and negative values are weaknesses (-0.5 means 50% more damage). Types that are not
+// listed deal their full damage.
+#[derive(Component, Deserialize, Clone, Debug, Default)]
+pub struct Resistances(pub HashMap<String, f32>);
+
+impl Resistances {
+    pub fn multiplier(&self, damage_type: &str) -> f32 {
+        1.0 - self.0.get(damage_type).copied().unwrap_or(0.0)
+    }
+}
+
+// How the damage of an attacker's hits is rolled.
+#[derive(Component, Deserialize, Clone, Debug)]
+pub struct DamageRoll {
+    // The chance (0 to 1) of a hit being critical.
+    pub crit_chance: f32,
+    pub crit_multiplier: f32,
+    // Hits deal up to this fraction more or less than their base damage.
+    pub variance: f32,
+}
+
+impl Default for DamageRoll {
+    fn default() -> Self {
+        Self {
+            crit_chance: 0.1,
+            crit_multiplier: 1.5,
+            variance: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DamageResult {
+    pub amount: f32,
+    pub critical: bool,
+}
+
+// Works out how much damage a hit really deals. Hits without an attacker's `DamageRoll` (hazards,
+// damage over time...) neither crit nor vary. This only does the math, so the random number
+// generator is passed in.
+pub fn calculate_damage(
+    base: f32,
+    damage_type: &str,
+    roll: Option<&DamageRoll>,
+    resistances: Option<&Resistances>,
+    rng: &mut impl Rng,
+) -> DamageResult {
+    let mut amount = base;
+    let mut critical = false;
+    if let Some(roll) = roll {
+        if 0.0 < roll.variance {
+            amount *= 1.0 + rng.gen_range(-roll.variance..=roll.variance);
+        }
+        if rng.gen_bool(roll.crit_chance.clamp(0.0, 1.0) as f64) {
+            amount *= roll.crit_multiplier;
+            critical = true;
+        }
+    }
+    if let Some(resistances) = resistances {
+        amount *= resistances.multiplier(damage_type);
+    }
+    DamageResult {
+        amount: amount.max(0.0),
+        critical,
+    }
+}
+
+fn load_damage_types(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(DamageTypeRegistryHandle(
+        asset_server.load("damage/types.damage.ron"),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    fn roll(crit_chance: f32, crit_multiplier: f32, variance: f32) -> DamageRoll {
+        DamageRoll {
+            crit_chance,
+            crit_multiplier,
+            variance,
+        }
+    }
+
+    fn resistances(entries: &[(&str, f32)]) -> Resistances {
+        Resistances(
+            entries
+                .iter()
+                .map(|(id, value)| (id.to_string(), *value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn hits_without_a_roll_deal_their_base_damage() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let result = calculate_damage(10.0, "physical", None, None, &mut rng);
+        assert_eq!(
+            result,
+            DamageResult {
+                amount: 10.0,
+                critical: false
+            }
+        );
+    }
+
+    #[test]
+    fn variance_stays_within_its_bounds() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let roll = roll(0.0, 2.0, 0.25);
+        let amounts: Vec<f32> = (0..1000)
+            .map(|_| calculate_damage(100.0, "physical", Some(&roll), None, &mut rng).amount)
+            .collect();
+        assert!(amounts.iter().all(|amount| (75.0..=125.0).contains(amount)));
+        // The roll does vary, both ways.
+        assert!(amounts.iter().any(|amount| *amount < 95.0));
+        assert!(amounts.iter().any(|amount| 105.0 < *amount));
+    }
+
+    #[test]
+    fn crits_multiply_the_damage() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let always = roll(1.0, 2.5, 0.0);
+        let result = calculate_damage(10.0, "physical", Some(&always), None, &mut rng);
+        assert_eq!(
+            result,
+            DamageResult {
+                amount: 25.0,
+                critical: true
+            }
+        );
+
+        let never = roll(0.0, 2.5, 0.0);
+        let result = calculate_damage(10.0, "physical", Some(&never), None, &mut rng);
+        assert!(!result.critical);
+        assert_eq!(result.amount, 10.0);
+    }
+
+    #[test]
+    fn out_of_range_crit_chances_are_clamped() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let over = roll(3.0, 2.0, 0.0);
+        assert!(calculate_damage(10.0, "physical", Some(&over), None, &mut rng).critical);
+        let under = roll(-1.0, 2.0, 0.0);
+        assert!(!calculate_damage(10.0, "physical", Some(&under), None, &mut rng).critical);
+    }
+
+    #[test]
+    fn resistances_and_weaknesses_scale_the_damage() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let resistances = resistances(&[("fire", 0.5), ("ice", -0.5)]);
+        let mut amount = |damage_type: &str| {
+            calculate_damage(10.0, damage_type, None, Some(&resistances), &mut rng).amount
+        };
+        assert_eq!(amount("fire"), 5.0);
+        assert_eq!(amount("ice"), 15.0);
+        // Unlisted types deal their full damage.
+        assert_eq!(amount("physical"), 10.0);
+    }
+
+    #[test]
+    fn resistances_apply_after_the_crit() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let roll = roll(1.0, 2.0, 0.0);
+        let resistances = resistances(&[("fire", 0.5)]);
+        let result = calculate_damage(10.0, "fire", Some(&roll), Some(&resistances), &mut rng);
+        assert!(result.critical);
+        assert_eq!(result.amount, 10.0);
+    }
+
+    #[test]
+    fn immunity_never_heals() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let resistances = resistances(&[("poison", 1.0), ("holy", 1.5)]);
+        let roll = roll(1.0, 3.0, 0.2);
+        for damage_type in ["poison", "holy"] {
+            let result =
+                calculate_damage(10.0, damage_type, Some(&roll), Some(&resistances), &mut rng);
+            assert_eq!(result.amount, 0.0);
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::enemy::Enemy;
+use crate::health::{DeathEvent, Health, HealthSystems};
+use crate::juice::CameraShake;
+use crate::layers::GameLayer;
+use crate::perception::{PerceptionMemory, Stimulus};
+use crate::ron_asset::RonAssetLoader;
+use crate::{GameState, Player};
+
+pub struct EncounterPlugin;
+
+impl Plugin for EncounterPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<EncounterDef>()
+            .register_asset_loader(RonAssetLoader::<EncounterDef>::new(&["encounter.ron"]))
+            .init_resource::<CameraLock>()
+            .add_event::<EncounterStartedEvent>()
+            .add_event::<WaveStartedEvent>()
+            .add_event::<EncounterClearedEvent>()
+            .add_event::<EncounterResetEvent>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    reset_encounters,
+                    start_encounters,
+                    run_encounters,
+                    alert_encounter_enemies,
+                    toggle_gates,
+                )
+                    .chain()
+                    // Enemies killed this tick no longer count as alive.
+                    .after(HealthSystems)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(Update, move_locked_camera);
+    }
+}
+
+// A sequence of enemy waves, loaded from an `.encounter.ron` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct EncounterDef {
+    pub waves: Vec<WaveDef>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WaveDef {
+    #[serde(default)]
+    pub condition: WaveCondition,
+    // Seconds between the condition being met and the wave starting.
+    #[serde(default)]
+    pub delay: f32,
+    pub spawns: Vec<SpawnDef>,
+}
+
+// What has to happen before a wave starts.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub enum WaveCondition {
+    // Every enemy of the earlier waves is dead.
+    #[default]
+    Cleared,
+    // At most this many enemies of the earlier waves are left.
+    AliveAtMost(usize),
+    // Nothing - only the delay (counted from the previous wave) holds the wave back.
+    Immediately,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SpawnDef {
+    // The id of the `Spawner` the enemies appear at.
+    pub spawner: String,
+    // The path of an `.enemy.ron` file.
+    pub enemy: String,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    // Seconds between the enemies of this spawn.
+    #[serde(default)]
+    pub interval: f32,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+// A point enemies of encounters appear at, referred to by its id in the encounter data.
+#[derive(Component, Debug)]
+pub struct Spawner {
+    pub id: String,
+}
+
+// Walking into an arena locks the player in and starts its encounter. Once every wave is beaten the
+// arena opens up again, for good.
+#[derive(Component, Debug)]
+pub struct Arena {
+    pub encounter: Handle<EncounterDef>,
+    // Where the camera is held during the fight.
+    pub camera: Transform,
+}
+
+impl Arena {
+    // Everything an arena needs besides its transform and collider (which is the trigger volume).
+    pub fn bundle(encounter: Handle<EncounterDef>, camera: Transform) -> impl Bundle {
+        (
+            Arena { encounter, camera },
+            ArenaState::default(),
+            Sensor,
+            CollidingEntities::default(),
+            CollisionLayers::new(GameLayer::Trigger, [GameLayer::Default]),
+        )
+    }
+}
+
+#[derive(Component, Default, Debug)]
+pub struct ArenaState {
+    pub phase: ArenaPhase,
+    // The index of the next wave to start.
+    pub next_wave: usize,
+    // Seconds since the last wave started.
+    pub since_wave: f32,
+    // Counts the next wave's delay down, once its condition is met.
+    pub until_wave: Option<f32>,
+    pending: Vec<PendingSpawn>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArenaPhase {
+    #[default]
+    Waiting,
+    Fighting,
+    Cleared,
+}
+
+// The enemies of a spawn that haven't appeared yet.
+#[derive(Debug)]
+struct PendingSpawn {
+    spawn: SpawnDef,
+    remaining: u32,
+    until_next: f32,
+}
+
+// A wall that blocks an exit of the arena while its encounter is going on. Spawn it with a collider
+// (and a mesh), it starts out open.
+#[derive(Component, Debug)]
+pub struct ArenaGate {
+    pub arena: Entity,
+}
+
+impl ArenaGate {
+    pub fn bundle(arena: Entity) -> impl Bundle {
+        (
+            ArenaGate { arena },
+            RigidBody::Static,
+            ColliderDisabled,
+            Visibility::Hidden,
+        )
+    }
+}
+
+// Marks the enemies spawned by an arena, so that it knows when they are all dead.
+#[derive(Component, Debug)]
+pub struct EncounterEnemy {
+    pub arena: Entity,
+    alerted: bool,
+}
+
+// Where the camera is held, if anywhere. Set while an encounter is going on.
+#[derive(Resource, Default, Debug)]
+pub struct CameraLock {
+    pub target: Option<Transform>,
+    // Where the camera was before it got locked, to go back to.
+    saved: Option<Transform>,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct EncounterStartedEvent {
+    pub arena: Entity,
+}
+
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "nothing announces the waves yet")]
+pub struct WaveStartedEvent {
+    pub arena: Entity,
+    pub wave: usize,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct EncounterClearedEvent {
+    pub arena: Entity,
+}
+
+// Sent when the player dies during an encounter. The arena opens and waits for the player again.
+#[derive(Event, Debug, Clone)]
+pub struct EncounterResetEvent {
+    pub arena: Entity,
+}
+
+fn reset_encounters(
+    mut commands: Commands,
+    mut death_reader: EventReader<DeathEvent>,
+    player_query: Query<(), With<Player>>,
+    mut arenas_query: Query<(Entity, &mut ArenaState)>,
+    enemies_query: Query<(Entity, &EncounterEnemy)>,
+    mut lock: ResMut<CameraLock>,
+    mut reset_writer: EventWriter<EncounterResetEvent>,
+) {
+    if !death_reader
+        .read()
+        .any(|event| player_query.contains(event.entity))
+    {
+        return;
+    }
+    for (arena, mut state) in arenas_query.iter_mut() {
+        if state.phase != ArenaPhase::Fighting {
+            continue;
+        }
+        *state = ArenaState::default();
+        for (enemy, encounter_enemy) in enemies_query.iter() {
+            if encounter_enemy.arena == arena {
+                commands.entity(enemy).despawn();
+            }
+        }
+        lock.target = None;
+        reset_writer.write(EncounterResetEvent { arena });
+    }
+}
+
+fn start_encounters(
+    player_query: Query<Entity, With<Player>>,
+    encounters: Res<Assets<EncounterDef>>,
+    mut arenas_query: Query<(Entity, &Arena, &mut ArenaState, &CollidingEntities)>,
+    mut lock: ResMut<CameraLock>,
+    mut started_writer: EventWriter<EncounterStartedEvent>,
+) {
+    let Ok(player) = player_query.single() else {
+        return;
+    };
+    for (entity, arena, mut state, colliding_entities) in arenas_query.iter_mut() {
+        if state.phase != ArenaPhase::Waiting
+            || !colliding_entities.contains(&player)
+            || !encounters.contains(&arena.encounter)
+        {
+            continue;
+        }
+        state.phase = ArenaPhase::Fighting;
+        lock.target = Some(arena.camera);
+        started_writer.write(EncounterStartedEvent { arena: entity });
+    }
+}
+
+fn run_encounters(
+    time: Res<Time>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    encounters: Res<Assets<EncounterDef>>,
+    mut arenas_query: Query<(Entity, &Arena, &mut ArenaState)>,
+    spawners_query: Query<(&Spawner, &GlobalTransform)>,
+    enemies_query: Query<(&EncounterEnemy, Option<&Health>)>,
+    mut lock: ResMut<CameraLock>,
+    mut wave_writer: EventWriter<WaveStartedEvent>,
+    mut cleared_writer: EventWriter<EncounterClearedEvent>,
+) {
+    let dt = time.delta_secs();
+    for (entity, arena, mut state) in arenas_query.iter_mut() {
+        if state.phase != ArenaPhase::Fighting {
+            continue;
+        }
+        let Some(encounter) = encounters.get(&arena.encounter) else {
+            continue;
+        };
+        state.since_wave += dt;
+
+        // Enemies still waiting to appear count as alive.
+        let alive = enemies_query
+            .iter()
+            .filter(|(enemy, health)| {
+                enemy.arena == entity && !health.is_some_and(|health| health.is_dead())
+            })
+            .count()
+            + state
+                .pending
+                .iter()
+                .map(|pending| pending.remaining as usize)
+                .sum::<usize>();
+
+        if let Some(wave) = encounter.waves.get(state.next_wave) {
+            let ready = match wave.condition {
+                WaveCondition::Cleared => alive == 0,
+                WaveCondition::AliveAtMost(count) => alive <= count,
+                WaveCondition::Immediately => true,
+            };
+            match state.until_wave {
+                None if ready => state.until_wave = Some(wave.delay),
+                Some(remaining) => state.until_wave = Some(remaining - dt),
+                None => {}
+            }
+            if state.until_wave.is_some_and(|remaining| remaining <= 0.0) {
+                state
+                    .pending
+                    .extend(wave.spawns.iter().map(|spawn| PendingSpawn {
+                        spawn: spawn.clone(),
+                        remaining: spawn.count,
+                        until_next: 0.0,
+                    }));
+                wave_writer.write(WaveStartedEvent {
+                    arena: entity,
+                    wave: state.next_wave,
+                });
+                state.next_wave += 1;
+                state.since_wave = 0.0;
+                state.until_wave = None;
+            }
+        } else if alive == 0 {
+            state.phase = ArenaPhase::Cleared;
+            lock.target = None;
+            cleared_writer.write(EncounterClearedEvent { arena: entity });
+            continue;
+        }
+
+        for pending in state.pending.iter_mut() {
+            pending.until_next -= dt;
+            if 0.0 < pending.until_next {
+                continue;
+            }
+            pending.remaining -= 1;
+            pending.until_next = pending.spawn.interval;
+            let Some((_, spawner_transform)) = spawners_query
+                .iter()
+                .find(|(spawner, _)| spawner.id == pending.spawn.spawner)
+            else {
+                warn!("No spawner with the id {:?}", pending.spawn.spawner);
+                continue;
+            };
+            commands.spawn((
+                Enemy {
+                    def: asset_server.load(pending.spawn.enemy.clone()),
+                },
+                Transform::from_translation(spawner_transform.translation()),
+                EncounterEnemy {
+                    arena: entity,
+                    alerted: false,
+                },
+            ));
+        }
+        state.pending.retain(|pending| 0 < pending.remaining);
+    }
+}
+
+// Enemies of an encounter know where the player is from the start - there's no sneaking past them.
+fn alert_encounter_enemies(
+    player_query: Query<&GlobalTransform, With<Player>>,
+    mut query: Query<(&mut EncounterEnemy, &mut PerceptionMemory)>,
+) {
+    let Ok(player_transform) = player_query.single() else {
+        return;
+    };
+    for (mut encounter_enemy, mut memory) in query.iter_mut() {
+        if !encounter_enemy.alerted {
+            encounter_enemy.alerted = true;
+            memory.remember(player_transform.translation(), Stimulus::Alert);
+        }
+    }
+}
+
+fn toggle_gates(
+    mut commands: Commands,
+    mut started_reader: EventReader<EncounterStartedEvent>,
+    mut cleared_reader: EventReader<EncounterClearedEvent>,
+    mut reset_reader: EventReader<EncounterResetEvent>,
+    gates_query: Query<(Entity, &ArenaGate)>,
+) {
+    let closed = started_reader.read().map(|event| (event.arena, true));
+    let opened = cleared_reader
+        .read()
+        .map(|event| event.arena)
+        .chain(reset_reader.read().map(|event| event.arena))
+        .map(|arena| (arena, false));
+    for (arena, close) in closed.chain(opened) {
+        for (gate, arena_gate) in gates_query.iter() {
+            if arena_gate.arena != arena {
+                continue;
+            }
+            if close {
+                commands
+                    .entity(gate)
+                    .remove::<ColliderDisabled>()
+                    .insert(Visibility::Inherited);
+            } else {
+                commands
+                    .entity(gate)
+                    .insert((ColliderDisabled, Visibility::Hidden));
+            }
+        }
+    }
+}
+
+// Eases the camera to where the lock holds it, and back once released.
+fn move_locked_camera(
+    time: Res<Time>,
+    mut lock: ResMut<CameraLock>,
+    mut cameras_query: Query<(&mut CameraShake, &Transform)>,
+) {
+    const EASING: f32 = 4.0;
+    let Ok((mut shake, transform)) = cameras_query.single_mut() else {
+        return;
+    };
+    let current = shake.base().unwrap_or(*transform);
+    let goal = match (lock.target, lock.saved) {
+        (Some(target), None) => {
+            lock.saved = Some(current);
+            target
+        }
+        (Some(target), Some(_)) => target,
+        (None, Some(saved)) => {
+            if current.translation.distance(saved.translation) < 0.01 {
+                lock.saved = None;
+            }
+            saved
+        }
+        (None, None) => return,
+    };
+    let t = 1.0 - (-EASING * time.delta_secs()).exp();
+    shake.set_base(Transform {
+        translation: current.translation.lerp(goal.translation, t),
+        rotation: current.rotation.slerp(goal.rotation, t),
+        scale: current.scale,
+    });
+}
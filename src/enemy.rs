@@ -0,0 +1,455 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::TnuaAnimatingState;
+use bevy_tnua::prelude::*;
+use bevy_tnua_avian3d::*;
+use serde::Deserialize;
+
+use crate::behavior::BehaviorRunner;
+use crate::damage::Resistances;
+use crate::flying::{Flight, flyer_bundle};
+use crate::health::{Health, Loot, LootDrop};
+use crate::knockback::{HitStun, Knockback};
+use crate::layers::GameLayer;
+use crate::melee::{AttackInput, AttackState, Hurtbox, MeleeAttack, MeleeAttacker};
+use crate::navigation::NavAgent;
+use crate::perception::{Perception, PerceptionMemory, PerceptionSystems};
+use crate::progression::XpReward;
+use crate::ron_asset::RonAssetLoader;
+use crate::status::StatusEffects;
+use crate::{AnimationState, Facing, GameState};
+
+pub struct EnemyPlugin;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnemyThinkSystems;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<EnemyDef>()
+            .register_asset_loader(RonAssetLoader::<EnemyDef>::new(&["enemy.ron"]))
+            .add_systems(Update, build_enemies)
+            .add_systems(
+                FixedUpdate,
+                (
+                    think
+                        .in_set(EnemyThinkSystems)
+                        .after(PerceptionSystems)
+                        .before(TnuaUserControlsSystemSet),
+                    move_enemies.in_set(TnuaUserControlsSystemSet),
+                )
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// Describes a kind of enemy, loaded from a `.enemy.ron` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct EnemyDef {
+    pub health: f32,
+    pub color: [f32; 3],
+    // The enemy's body is a capsule.
+    pub radius: f32,
+    pub height: f32,
+    // Must be a bit more than the distance between the center and the bottom of the capsule. Left
+    // out, it's worked out from the `height`.
+    #[serde(default)]
+    pub float_height: Option<f32>,
+    pub ai: EnemyAi,
+    pub perception: Perception,
+    pub attack: MeleeAttack,
+    #[serde(default)]
+    pub resistances: Resistances,
+    #[serde(default)]
+    pub loot: Vec<LootDrop>,
+    // Given to whoever kills the enemy.
+    #[serde(default)]
+    pub xp: u32,
+    // Whether the enemy finds its way to the player over the level, instead of stopping at ledges.
+    #[serde(default)]
+    pub navigates: bool,
+    // The path of a `.bt.ron` behavior tree that replaces the state machine.
+    #[serde(default)]
+    pub behavior: Option<String>,
+    // Makes the enemy fly instead of walking. `float_height`, `navigates` and `behavior` don't
+    // apply to flyers.
+    #[serde(default)]
+    pub flight: Option<Flight>,
+}
+
+impl EnemyDef {
+    // How high over the ground the walk basis holds the enemy's center.
+    pub fn float_height(&self) -> f32 {
+        self.float_height
+            .unwrap_or(self.height * 0.5 + FLOAT_HEIGHT_MARGIN)
+    }
+}
+
+// How much higher than the bottom of its capsule an enemy floats, unless its definition says.
+const FLOAT_HEIGHT_MARGIN: f32 = 0.5;
+
+// The parameters of the enemy state machine.
+#[derive(Deserialize, Clone, Debug)]
+pub struct EnemyAi {
+    pub patrol_speed: f32,
+    pub chase_speed: f32,
+    // The horizontal distance from which the enemy attacks.
+    pub attack_range: f32,
+    // Seconds between the end of an attack and the next one.
+    pub attack_cooldown: f32,
+    // How far the enemy chases the player away from its home before giving up.
+    pub leash_range: f32,
+}
+
+// Spawn this with a `Transform` (and optionally a `PatrolRoute`) to create an enemy. The rest of
+// its components are added once the definition is loaded.
+#[derive(Component, Debug)]
+pub struct Enemy {
+    pub def: Handle<EnemyDef>,
+}
+
+// Points the enemy walks between while patrolling. Without it, the enemy walks back and forth
+// between the ledges (or walls) of the ground it stands on.
+#[derive(Component, Default, Debug)]
+pub struct PatrolRoute(pub Vec<Vec3>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnemyMode {
+    Patrol,
+    Chase,
+    Attack,
+    // Going back home after losing the player.
+    Return,
+}
+
+#[derive(Component, Debug)]
+pub struct EnemyBrain {
+    pub ai: EnemyAi,
+    pub float_height: f32,
+    pub mode: EnemyMode,
+    // Where the enemy spawned, and returns to.
+    pub home: Vec3,
+    // The horizontal direction the enemy wants to walk in (-1, 0 or 1) and how fast.
+    pub heading: f32,
+    pub speed: f32,
+    // The height of the jump the enemy wants to make, if any.
+    pub jump: Option<f32>,
+    patrol_index: usize,
+    patrol_direction: f32,
+    cooldown: f32,
+}
+
+impl EnemyBrain {
+    fn new(ai: EnemyAi, float_height: f32, home: Vec3) -> Self {
+        Self {
+            ai,
+            float_height,
+            mode: EnemyMode::Patrol,
+            home,
+            heading: 0.0,
+            speed: 0.0,
+            jump: None,
+            patrol_index: 0,
+            patrol_direction: 1.0,
+            cooldown: 0.0,
+        }
+    }
+}
+
+fn build_enemies(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    defs: Res<Assets<EnemyDef>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    // Enemies get their health (among the rest) once built.
+    query: Query<(Entity, &Enemy, &Transform), Without<Health>>,
+) {
+    for (entity, enemy, transform) in query.iter() {
+        let Some(def) = defs.get(&enemy.def) else {
+            continue;
+        };
+        let [red, green, blue] = def.color;
+        let half_length = (def.height * 0.5 - def.radius).max(0.0);
+        let collider = Collider::capsule(def.radius, half_length * 2.0);
+        commands
+            .entity(entity)
+            .insert((
+                Mesh3d(meshes.add(Capsule3d::new(def.radius, half_length * 2.0))),
+                MeshMaterial3d(materials.add(Color::srgb(red, green, blue))),
+                RigidBody::Dynamic,
+                collider.clone(),
+            ))
+            .insert((
+                def.perception.clone(),
+                PerceptionMemory::default(),
+                Facing(-1.0),
+                MeleeAttacker::new(def.attack.clone()),
+                AttackState::default(),
+            ))
+            .insert((
+                Health::new(def.health),
+                Knockback::default(),
+                HitStun::default(),
+                StatusEffects::default(),
+                def.resistances.clone(),
+                Loot(def.loot.clone()),
+                XpReward(def.xp),
+            ));
+        commands.spawn((Hurtbox::bundle(entity, collider), ChildOf(entity)));
+
+        if let Some(flight) = &def.flight {
+            commands.entity(entity).insert(flyer_bundle(
+                flight.clone(),
+                def.ai.clone(),
+                transform.translation,
+            ));
+            continue;
+        }
+        commands.entity(entity).insert((
+            // The same controller stack as the player's.
+            TnuaController::default(),
+            TnuaAvian3dSensorShape(Collider::cylinder(def.radius - 0.01, 0.0)),
+            LockedAxes::ROTATION_LOCKED.unlock_rotation_y(),
+            EnemyBrain::new(def.ai.clone(), def.float_height(), transform.translation),
+        ));
+        if def.navigates {
+            commands
+                .entity(entity)
+                .insert(NavAgent::new(def.ai.chase_speed, def.float_height()));
+        }
+        if let Some(behavior) = &def.behavior {
+            commands.entity(entity).insert((
+                BehaviorRunner::new(asset_server.load(behavior.clone())),
+                // Like the flyers', set by the tree's `Animate` leaves.
+                TnuaAnimatingState::<AnimationState>::default(),
+            ));
+        }
+    }
+}
+
+// Runs the state machine of the enemies that don't have a behavior tree.
+fn think(
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+    characters_query: Query<(), With<Health>>,
+    mut query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &mut EnemyBrain,
+            &mut PerceptionMemory,
+            &mut AttackState,
+            Option<&PatrolRoute>,
+            Option<&mut NavAgent>,
+            Option<&StatusEffects>,
+        ),
+        Without<BehaviorRunner>,
+    >,
+) {
+    let is_level = |entity: Entity| !characters_query.contains(entity);
+
+    for (
+        entity,
+        transform,
+        mut brain,
+        mut memory,
+        mut attack_state,
+        route,
+        agent,
+        status_effects,
+    ) in query.iter_mut()
+    {
+        let position = transform.translation();
+        // A stunned enemy keeps its mind, but can't act on it.
+        let stunned = status_effects.is_some_and(StatusEffects::is_stunned);
+        let sees_player = memory.sees_player;
+        brain.jump = None;
+        brain.cooldown -= time.delta_secs();
+
+        let attacking = attack_state.active.is_some();
+        let away_from_home = (position.x - brain.home.x).abs();
+        let mode = brain.mode;
+        brain.mode = match mode {
+            // Whatever made the enemy aware of the player (even a noise), it goes to check.
+            EnemyMode::Patrol if memory.is_aware() => EnemyMode::Chase,
+            EnemyMode::Chase | EnemyMode::Attack
+                if !memory.is_aware() || brain.ai.leash_range < away_from_home =>
+            {
+                memory.forget();
+                EnemyMode::Return
+            }
+            EnemyMode::Chase => {
+                let in_range = memory.last_known.is_some_and(|target| {
+                    (target.x - position.x).abs() <= brain.ai.attack_range
+                        && (target.y - position.y).abs() <= brain.float_height
+                });
+                if sees_player && in_range && brain.cooldown <= 0.0 && !stunned {
+                    attack_state.request(AttackInput::Light);
+                    EnemyMode::Attack
+                } else {
+                    EnemyMode::Chase
+                }
+            }
+            // The attack input may wait in the buffer for a bit before the attack starts.
+            EnemyMode::Attack if attacking || attack_state.buffer.is_some() => EnemyMode::Attack,
+            EnemyMode::Attack => {
+                brain.cooldown = brain.ai.attack_cooldown;
+                EnemyMode::Chase
+            }
+            EnemyMode::Return if sees_player => EnemyMode::Chase,
+            EnemyMode::Return if (position.x - brain.home.x).abs() < 0.5 => EnemyMode::Patrol,
+            mode => mode,
+        };
+
+        // Decide where to go.
+        let (target, speed) = match brain.mode {
+            EnemyMode::Patrol => match route.filter(|route| !route.0.is_empty()) {
+                Some(route) => {
+                    let mut point = route.0[brain.patrol_index % route.0.len()];
+                    if (point.x - position.x).abs() < 0.3 {
+                        brain.patrol_index = (brain.patrol_index + 1) % route.0.len();
+                        point = route.0[brain.patrol_index];
+                    }
+                    (Some(point.x), brain.ai.patrol_speed)
+                }
+                None => (
+                    Some(position.x + brain.patrol_direction),
+                    brain.ai.patrol_speed,
+                ),
+            },
+            EnemyMode::Chase => (
+                memory.last_known.map(|target| target.x),
+                brain.ai.chase_speed,
+            ),
+            EnemyMode::Attack => (None, 0.0),
+            EnemyMode::Return => (Some(brain.home.x), brain.ai.patrol_speed),
+        };
+        let mut heading = target.map_or(0.0, |target| {
+            let offset = target - position.x;
+            // Don't push into the player when close enough to hit it.
+            if offset.abs() < 0.3
+                || (brain.mode == EnemyMode::Chase && offset.abs() < brain.ai.attack_range * 0.5)
+            {
+                0.0
+            } else {
+                offset.signum()
+            }
+        });
+
+        // Enemies that can navigate find their way to the player (or back home) over the level,
+        // jumping and dropping down as needed - the path already knows where the ledges are. Once
+        // the path runs out (e.g. on the same platform as the player) they head straight there.
+        if let Some(mut agent) = agent {
+            agent.target = match brain.mode {
+                EnemyMode::Chase => memory.last_known,
+                EnemyMode::Return => Some(brain.home),
+                EnemyMode::Patrol | EnemyMode::Attack => None,
+            };
+            if agent.target.is_some() && !agent.path.is_empty() {
+                brain.heading = agent.heading;
+                brain.jump = agent.jump.filter(|_| !stunned);
+                brain.speed = speed;
+                continue;
+            }
+        }
+
+        // Turn around at ledges and walls.
+        if heading != 0.0
+            && blocked_ahead(
+                &spatial_query,
+                entity,
+                position,
+                heading,
+                brain.float_height,
+                &is_level,
+            )
+        {
+            if brain.mode == EnemyMode::Patrol {
+                brain.patrol_direction = -heading;
+                if let Some(route) = route.filter(|route| !route.0.is_empty()) {
+                    brain.patrol_index = (brain.patrol_index + 1) % route.0.len();
+                }
+            }
+            heading = 0.0;
+        }
+        brain.heading = heading;
+        brain.speed = speed;
+    }
+}
+
+// Whether walking in the `heading` direction would bump into a wall or walk off a ledge.
+pub fn blocked_ahead(
+    spatial_query: &SpatialQuery,
+    entity: Entity,
+    position: Vec3,
+    heading: f32,
+    float_height: f32,
+    is_level: &dyn Fn(Entity) -> bool,
+) -> bool {
+    let filter = SpatialQueryFilter::from_excluded_entities([entity]).with_mask(GameLayer::Default);
+    let ahead = if 0.0 < heading { Dir3::X } else { Dir3::NEG_X };
+    let wall = spatial_query
+        .cast_ray_predicate(position, ahead, 1.0, true, &filter, is_level)
+        .is_some();
+    let floor = spatial_query
+        .cast_ray_predicate(
+            position + *ahead,
+            Dir3::NEG_Y,
+            float_height + 1.0,
+            true,
+            &filter,
+            is_level,
+        )
+        .is_some();
+    wall || !floor
+}
+
+fn move_enemies(
+    mut query: Query<(
+        &mut TnuaController,
+        &EnemyBrain,
+        &mut Facing,
+        &Knockback,
+        &HitStun,
+        Option<&StatusEffects>,
+        &AttackState,
+    )>,
+) {
+    for (mut controller, brain, mut facing, knockback, hit_stun, status_effects, attack_state) in
+        query.iter_mut()
+    {
+        // Like the player, a stunned enemy has no control over its movement.
+        if hit_stun.is_stunned() {
+            controller.basis(TnuaBuiltinWalk {
+                float_height: brain.float_height,
+                acceleration: knockback.stunned_acceleration,
+                air_acceleration: knockback.stunned_acceleration,
+                ..Default::default()
+            });
+            continue;
+        }
+        let frozen = status_effects.is_some_and(StatusEffects::is_stunned);
+        let speed_multiplier = status_effects.map_or(1.0, StatusEffects::speed_multiplier);
+        let heading = if frozen { 0.0 } else { brain.heading };
+
+        // Attacks keep the direction they started in.
+        if heading != 0.0 && attack_state.active.is_none() {
+            facing.0 = heading;
+        }
+        let direction = Vec3::X * heading;
+        controller.basis(TnuaBuiltinWalk {
+            desired_velocity: direction * brain.speed * speed_multiplier,
+            desired_forward: Dir3::new(direction).ok(),
+            float_height: brain.float_height,
+            ..Default::default()
+        });
+        // Like the player's jump button, the jump is fed for as long as the AI wants it.
+        if let Some(height) = brain.jump.filter(|_| !frozen) {
+            controller.action(TnuaBuiltinJump {
+                height,
+                ..Default::default()
+            });
+        }
+    }
+}
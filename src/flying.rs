@@ -0,0 +1,299 @@
+use std::f32::consts::TAU;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::TnuaAnimatingState;
+use serde::Deserialize;
+
+use crate::enemy::EnemyAi;
+use crate::health::Health;
+use crate::knockback::{HitStun, KnockbackSystems};
+use crate::layers::GameLayer;
+use crate::melee::{AttackInput, AttackState};
+use crate::perception::{PerceptionMemory, PerceptionSystems};
+use crate::status::StatusEffects;
+use crate::{AnimationState, Facing, GameState};
+
+pub struct FlyingPlugin;
+
+impl Plugin for FlyingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (fly, animate_flyers)
+                .chain()
+                .after(PerceptionSystems)
+                // Knockback takes over the velocity, so let it land first.
+                .after(KnockbackSystems)
+                .run_if(in_state(GameState::Ready)),
+        );
+    }
+}
+
+// How a flying enemy moves. Tnua's walk basis needs ground to float above, so flyers don't use it -
+// they have no gravity and steer their velocity directly.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Flight {
+    // How quickly the flyer changes its velocity.
+    pub acceleration: f32,
+    // The pattern flown around the flyer's home while it's unaware of the player.
+    pub idle: FlightPattern,
+    // The pattern flown around where the player is (or was last known to be).
+    pub engaged: FlightPattern,
+}
+
+// A flight pattern is flown around an anchor - the flyer's home, or the player.
+#[derive(Deserialize, Clone, Debug)]
+pub enum FlightPattern {
+    // Stays `height` above the anchor, bobbing up and down.
+    Hover {
+        height: f32,
+        amplitude: f32,
+        // Bobs per second.
+        frequency: f32,
+    },
+    // Flies back and forth within `range` of the anchor, waving up and down.
+    SineWave {
+        range: f32,
+        amplitude: f32,
+        frequency: f32,
+    },
+    // Flies through the points (relative to the anchor) in order, then starts over.
+    Path(Vec<Vec3>),
+    // Circles `height` above the anchor and every `interval` seconds dives through it.
+    Swoop {
+        height: f32,
+        interval: f32,
+        dive_speed: f32,
+    },
+}
+
+// Added to enemies whose definition has a `flight`, in place of the grounded enemies' `EnemyBrain`.
+#[derive(Component, Debug)]
+pub struct Flyer {
+    pub flight: Flight,
+    pub ai: EnemyAi,
+    pub home: Vec3,
+    // Whether the flyer is flying its engaged pattern.
+    pub engaged: bool,
+    // Seconds since the flyer switched patterns. Drives the waves.
+    pub elapsed: f32,
+    // The point being dived at, and how long the dive may still take.
+    pub dive: Option<(Vec3, f32)>,
+    path_index: usize,
+    wave_direction: f32,
+    until_dive: f32,
+    cooldown: f32,
+}
+
+impl Flyer {
+    pub fn new(flight: Flight, ai: EnemyAi, home: Vec3) -> Self {
+        Self {
+            flight,
+            ai,
+            home,
+            engaged: false,
+            elapsed: 0.0,
+            dive: None,
+            path_index: 0,
+            wave_direction: 1.0,
+            until_dive: 0.0,
+            cooldown: 0.0,
+        }
+    }
+}
+
+// Everything a flying enemy needs besides what all enemies have.
+pub fn flyer_bundle(flight: Flight, ai: EnemyAi, home: Vec3) -> impl Bundle {
+    (
+        Flyer::new(flight, ai, home),
+        GravityScale(0.0),
+        // Lets knockback die down while the flyer is stunned.
+        LinearDamping(3.0),
+        LockedAxes::ROTATION_LOCKED,
+        TnuaAnimatingState::<AnimationState>::default(),
+    )
+}
+
+fn fly(
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+    characters_query: Query<(), With<Health>>,
+    mut query: Query<(
+        &GlobalTransform,
+        &mut Flyer,
+        &mut PerceptionMemory,
+        &mut LinearVelocity,
+        &mut Facing,
+        &mut AttackState,
+        &HitStun,
+        Option<&StatusEffects>,
+    )>,
+) {
+    let dt = time.delta_secs();
+    // Level geometry only - the flyer itself and other characters don't count as walls.
+    let level_filter = SpatialQueryFilter::from_mask(GameLayer::Default);
+    let is_level = |entity: Entity| !characters_query.contains(entity);
+
+    for (
+        transform,
+        mut flyer,
+        mut memory,
+        mut velocity,
+        mut facing,
+        mut attack_state,
+        hit_stun,
+        status_effects,
+    ) in query.iter_mut()
+    {
+        let flyer = &mut *flyer;
+        let position = transform.translation();
+        flyer.elapsed += dt;
+        flyer.cooldown -= dt;
+
+        // Like grounded enemies, flyers give up when lured too far from home.
+        if memory.is_aware() && flyer.ai.leash_range < position.distance(flyer.home) {
+            memory.forget();
+        }
+        let engaged = memory.is_aware();
+        if engaged != flyer.engaged {
+            flyer.engaged = engaged;
+            flyer.elapsed = 0.0;
+            flyer.dive = None;
+            flyer.path_index = 0;
+        }
+
+        // Like grounded characters, a stunned flyer has no control over its movement.
+        if hit_stun.is_stunned() {
+            flyer.dive = None;
+            continue;
+        }
+        let frozen = status_effects.is_some_and(StatusEffects::is_stunned);
+        let speed_multiplier = status_effects.map_or(1.0, StatusEffects::speed_multiplier);
+
+        let (anchor, pattern, mut speed) = match memory.last_known.filter(|_| engaged) {
+            Some(player) => (player, &flyer.flight.engaged, flyer.ai.chase_speed),
+            None => (flyer.home, &flyer.flight.idle, flyer.ai.patrol_speed),
+        };
+        let wave =
+            |amplitude: f32, frequency: f32| amplitude * (TAU * frequency * flyer.elapsed).sin();
+
+        let target = match pattern {
+            FlightPattern::Hover {
+                height,
+                amplitude,
+                frequency,
+            } => anchor + Vec3::Y * (height + wave(*amplitude, *frequency)),
+            FlightPattern::SineWave {
+                range,
+                amplitude,
+                frequency,
+            } => {
+                // Turn around at the ends of the range, and at walls.
+                let ahead = if 0.0 < flyer.wave_direction {
+                    Dir3::X
+                } else {
+                    Dir3::NEG_X
+                };
+                let wall = spatial_query
+                    .cast_ray_predicate(position, ahead, 1.0, true, &level_filter, &is_level)
+                    .is_some();
+                if wall || *range < (position.x - anchor.x) * flyer.wave_direction {
+                    flyer.wave_direction = -flyer.wave_direction;
+                }
+                Vec3::new(
+                    position.x + flyer.wave_direction * speed,
+                    anchor.y + wave(*amplitude, *frequency),
+                    0.0,
+                )
+            }
+            FlightPattern::Path(points) if !points.is_empty() => {
+                let mut point = anchor + points[flyer.path_index % points.len()];
+                if position.distance(point) < 0.3 {
+                    flyer.path_index = (flyer.path_index + 1) % points.len();
+                    point = anchor + points[flyer.path_index];
+                }
+                point
+            }
+            FlightPattern::Path(_) => anchor,
+            FlightPattern::Swoop {
+                height,
+                interval,
+                dive_speed,
+            } => match flyer.dive {
+                Some((point, remaining)) => {
+                    // The dive ends at the point, or when something got in the way.
+                    if position.distance(point) < 0.5 || remaining <= 0.0 {
+                        flyer.dive = None;
+                        flyer.until_dive = *interval;
+                    } else {
+                        flyer.dive = Some((point, remaining - dt));
+                    }
+                    speed = *dive_speed;
+                    point
+                }
+                None => {
+                    flyer.until_dive -= dt;
+                    // Only dive at what the flyer can see - not at a remembered position.
+                    if flyer.until_dive <= 0.0 && memory.sees_player {
+                        let duration = position.distance(anchor) / dive_speed + 0.5;
+                        flyer.dive = Some((anchor, duration));
+                    }
+                    anchor + Vec3::Y * *height
+                }
+            },
+        };
+
+        // Head for the target, slowing down over the last stretch so as not to overshoot it.
+        let desired = if frozen {
+            Vec3::ZERO
+        } else {
+            ((target - position) * 2.0).clamp_length_max(speed * speed_multiplier)
+        };
+        velocity.0 = velocity
+            .0
+            .move_towards(desired, flyer.flight.acceleration * dt)
+            .with_z(0.0);
+
+        // Face the player while engaged (so that attacks go its way), or else the way of flight.
+        let heading = match memory.last_known.filter(|_| engaged) {
+            Some(player) => player.x - position.x,
+            None => desired.x,
+        };
+        if 0.1 < heading.abs() && attack_state.active.is_none() {
+            facing.0 = heading.signum();
+        }
+
+        let in_range = memory.sees_player
+            && memory
+                .last_known
+                .is_some_and(|player| position.distance(player) <= flyer.ai.attack_range);
+        if !frozen && in_range && flyer.cooldown <= 0.0 && attack_state.active.is_none() {
+            attack_state.request(AttackInput::Light);
+            flyer.cooldown = flyer.ai.attack_cooldown;
+        }
+    }
+}
+
+// Flyers share the animation states of grounded characters, but only ever fly, attack and get hurt.
+fn animate_flyers(
+    mut query: Query<
+        (
+            &mut TnuaAnimatingState<AnimationState>,
+            &AttackState,
+            &HitStun,
+        ),
+        With<Flyer>,
+    >,
+) {
+    for (mut animating_state, attack_state, hit_stun) in query.iter_mut() {
+        let state = if hit_stun.is_stunned() {
+            AnimationState::Hurt
+        } else if let Some(active) = attack_state.active.as_ref() {
+            AnimationState::Attacking(active.current_frame().atlas_index)
+        } else {
+            AnimationState::Flying
+        };
+        animating_state.update_by_discriminant(state);
+    }
+}
@@ -0,0 +1,214 @@
+use bevy::prelude::*;
+
+use crate::GameState;
+use crate::health::HealthSystems;
+use crate::knockback::HitStun;
+use crate::melee::AttackState;
+
+pub struct GuardPlugin;
+
+impl Plugin for GuardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ParryEvent>()
+            .add_systems(
+                FixedUpdate,
+                (regenerate_stamina, update_guard)
+                    .chain()
+                    // The guard must be up before the hits of this tick are resolved. Knockback
+                    // runs between the hits and the controls, so this can't come after the
+                    // controls as well - it goes by the request the controls made last tick.
+                    .before(HealthSystems)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(
+                FixedUpdate,
+                stun_parried_attackers
+                    .after(HealthSystems)
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// Lets a character block hits coming from the direction it faces.
+#[derive(Component, Clone, Debug)]
+pub struct Guard {
+    // The fraction of the damage (and knockback) a block absorbs.
+    pub damage_reduction: f32,
+    // For how long (in seconds) after raising the guard a hit is parried instead of blocked.
+    pub parry_window: f32,
+    // For how long a parried attacker is stunned.
+    pub parry_stun: f32,
+    // Stamina spent per point of blocked damage. Running out of stamina breaks the guard.
+    pub stamina_per_damage: f32,
+    // For how long the guard can't be raised again after it was broken.
+    pub break_duration: f32,
+}
+
+impl Default for Guard {
+    fn default() -> Self {
+        Self {
+            damage_reduction: 0.8,
+            parry_window: 0.15,
+            parry_stun: 0.8,
+            stamina_per_damage: 1.5,
+            break_duration: 1.0,
+        }
+    }
+}
+
+#[derive(Component, Default, Debug)]
+pub struct GuardState {
+    // Set by the controls every frame the guard button is held.
+    pub requested: bool,
+    pub guarding: bool,
+    // Seconds since the guard was raised.
+    pub elapsed: f32,
+    // Seconds left until a broken guard can be raised again.
+    pub broken: f32,
+}
+
+impl GuardState {
+    pub fn is_parrying(&self, guard: &Guard) -> bool {
+        self.guarding && self.elapsed <= guard.parry_window
+    }
+
+    // Decides what happens to a hit of `amount` damage. `from` is the horizontal direction the hit
+    // comes from, relative to the guarding character, and `facing` the direction it faces.
+    pub fn resolve_hit(
+        &mut self,
+        guard: &Guard,
+        stamina: &mut Stamina,
+        facing: f32,
+        from: f32,
+        amount: f32,
+    ) -> GuardOutcome {
+        // Hits from behind (or from nowhere in particular, like damage over time) can't be guarded.
+        if !self.guarding || from * facing <= 0.0 {
+            return GuardOutcome::Unguarded;
+        }
+        if self.is_parrying(guard) {
+            return GuardOutcome::Parried;
+        }
+        let blocked = amount * guard.damage_reduction;
+        if stamina.spend(blocked * guard.stamina_per_damage) {
+            GuardOutcome::Blocked {
+                multiplier: 1.0 - guard.damage_reduction,
+            }
+        } else {
+            // Out of stamina - the guard breaks and the hit goes through.
+            self.guarding = false;
+            self.broken = guard.break_duration;
+            GuardOutcome::Unguarded
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GuardOutcome {
+    Unguarded,
+    // Damage and knockback are multiplied by `multiplier`.
+    Blocked { multiplier: f32 },
+    Parried,
+}
+
+// Spent by guarding (and later by other actions). Regenerates after not being spent for a while.
+#[derive(Component, Clone, Debug)]
+pub struct Stamina {
+    pub current: f32,
+    pub max: f32,
+    // Per second.
+    pub regen_rate: f32,
+    // Seconds after spending stamina before it starts regenerating.
+    pub regen_delay: f32,
+    until_regen: f32,
+}
+
+impl Stamina {
+    pub fn new(max: f32, regen_rate: f32, regen_delay: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            regen_rate,
+            regen_delay,
+            until_regen: 0.0,
+        }
+    }
+
+    // Returns whether there was enough stamina. If there wasn't, what was left is spent anyway.
+    pub fn spend(&mut self, amount: f32) -> bool {
+        self.until_regen = self.regen_delay;
+        let enough = amount <= self.current;
+        self.current = (self.current - amount).max(0.0);
+        enough
+    }
+}
+
+// Sent when a hit is parried. `attacker` is `None` for parried projectiles, which are reflected
+// instead.
+#[derive(Event, Debug, Clone)]
+pub struct ParryEvent {
+    pub defender: Entity,
+    pub attacker: Option<Entity>,
+}
+
+fn regenerate_stamina(time: Res<Time>, mut query: Query<&mut Stamina>) {
+    for mut stamina in query.iter_mut() {
+        if 0.0 < stamina.until_regen {
+            stamina.until_regen -= time.delta_secs();
+        } else {
+            stamina.current =
+                (stamina.current + stamina.regen_rate * time.delta_secs()).min(stamina.max);
+        }
+    }
+}
+
+fn update_guard(
+    time: Res<Time>,
+    mut query: Query<(
+        &mut GuardState,
+        Option<&Stamina>,
+        Option<&HitStun>,
+        Option<&AttackState>,
+    )>,
+) {
+    for (mut state, stamina, hit_stun, attack_state) in query.iter_mut() {
+        if 0.0 < state.broken {
+            state.broken -= time.delta_secs();
+        }
+        // The guard can't be raised while stunned, in the middle of an attack, or without stamina.
+        let can_guard = state.broken <= 0.0
+            && !hit_stun.is_some_and(HitStun::is_stunned)
+            && attack_state.is_none_or(|attack| attack.active.is_none())
+            && stamina.is_none_or(|stamina| 0.0 < stamina.current);
+        if state.requested && can_guard {
+            if state.guarding {
+                state.elapsed += time.delta_secs();
+            } else {
+                state.guarding = true;
+                state.elapsed = 0.0;
+            }
+        } else {
+            state.guarding = false;
+        }
+    }
+}
+
+fn stun_parried_attackers(
+    mut parry_reader: EventReader<ParryEvent>,
+    guards_query: Query<&Guard>,
+    mut stun_query: Query<&mut HitStun>,
+) {
+    for event in parry_reader.read() {
+        let Some(attacker) = event.attacker else {
+            continue;
+        };
+        let (Ok(guard), Ok(mut hit_stun)) = (
+            guards_query.get(event.defender),
+            stun_query.get_mut(attacker),
+        ) else {
+            continue;
+        };
+        // Being stunned also interrupts the attacker's attack (see `advance_attacks`).
+        hit_stun.remaining = hit_stun.remaining.max(guard.parry_stun);
+    }
+}
@@ -0,0 +1,876 @@
+use avian3d::prelude::*;
+use bevy::ecs::entity::EntityHashMap;
+use bevy::ecs::query::QueryItem;
+use bevy::prelude::*;
+use bevy_egui::{EguiContextPass, EguiContexts, egui};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::climb::ClimbState;
+use crate::damage::{
+    DamageRoll, DamageTypeRegistry, DamageTypeRegistryHandle, Resistances, calculate_damage,
+};
+use crate::guard::{Guard, GuardOutcome, GuardState, ParryEvent, Stamina};
+use crate::knockback::{HitStun, KnockbackEvent};
+use crate::layers::GameLayer;
+use crate::ledge::LedgeState;
+use crate::stats::DerivedStats;
+use crate::status::{ApplyStatusEvent, StatusEffects};
+use crate::wall::WallState;
+use crate::water::{SwimState, Swimmer};
+use crate::{Facing, GameState, Player};
+
+pub struct HealthPlugin;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HealthSystems;
+
+// The part of `HealthSystems` that deals the damage and sends the `DeathEvent`s.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DamageSystems;
+
+// The part of `HealthSystems` that reacts to the deaths, despawning the dead. Systems that need to
+// look at an entity that just died go `.after(DamageSystems).before(DeathSystems)`.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeathSystems;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .add_event::<DamageDealtEvent>()
+            .add_event::<LootDropEvent>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    tick_invulnerability,
+                    (apply_contact_damage, apply_damage)
+                        .chain()
+                        .in_set(DamageSystems),
+                    (handle_player_death, handle_death).in_set(DeathSystems),
+                    update_checkpoints,
+                )
+                    .chain()
+                    .in_set(HealthSystems)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(
+                Update,
+                restart_after_game_over.run_if(in_state(GameState::GameOver)),
+            )
+            .add_systems(
+                EguiContextPass,
+                show_game_over.run_if(in_state(GameState::GameOver)),
+            );
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+
+    // Returns how much health was actually lost, which can be less than `amount` when the
+    // remaining health is lower than that.
+    pub fn apply_damage(&mut self, amount: f32) -> f32 {
+        let dealt = amount.max(0.0).min(self.current.max(0.0));
+        self.current -= dealt;
+        dealt
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+        if !self.is_dead() {
+            self.current = (self.current + amount.max(0.0)).min(self.max);
+        }
+    }
+
+    pub fn restore(&mut self) {
+        self.current = self.max;
+    }
+}
+
+// Damage dealt on contact by hazards (spikes, lava...). Needs `CollidingEntities` on the same
+// entity.
+#[derive(Component, Clone, Debug)]
+pub struct Damage {
+    pub amount: f32,
+    // The strength of the impulse pushing the damaged entity away.
+    pub knockback: f32,
+    pub damage_type: String,
+    // Ids of the status effects inflicted along with the damage.
+    pub inflicts: Vec<String>,
+    // Seconds before the hazard can hit the same entity again.
+    pub interval: f32,
+}
+
+impl Damage {
+    // Everything a hazard volume needs besides its transform and collider.
+    pub fn hazard_bundle(self) -> impl Bundle {
+        (
+            self,
+            HazardHits::default(),
+            Sensor,
+            CollidingEntities::default(),
+            CollisionLayers::new(GameLayer::Trigger, [GameLayer::Default]),
+        )
+    }
+}
+
+// The entities a hazard hit recently, with the seconds left until it can hit each of them again.
+#[derive(Component, Default, Debug)]
+pub struct HazardHits(EntityHashMap<f32>);
+
+// After being damaged, an entity with this component ignores further damage for a while.
+#[derive(Component, Clone, Debug)]
+pub struct Invulnerability {
+    pub duration: f32,
+    pub remaining: f32,
+}
+
+impl Invulnerability {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            remaining: 0.0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        0.0 < self.remaining
+    }
+}
+
+// Dying entities with this component are left alone by `handle_death` - whatever added it plays out
+// the death (and despawns the entity) itself.
+#[derive(Component, Debug)]
+pub struct DeathSequence;
+
+// What a non-player entity leaves behind when it dies.
+#[derive(Component, Clone, Debug, Default)]
+pub struct Loot(pub Vec<LootDrop>);
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LootDrop {
+    pub item: String,
+    pub count: u32,
+}
+
+// Where the player comes back after dying, and how many more times it can.
+#[derive(Component, Clone, Debug)]
+pub struct Respawn {
+    pub checkpoint: Vec3,
+    pub lives: u32,
+    // What `lives` goes back to when restarting after a game over.
+    pub max_lives: u32,
+}
+
+impl Respawn {
+    pub fn new(checkpoint: Vec3, lives: u32) -> Self {
+        Self {
+            checkpoint,
+            lives,
+            max_lives: lives,
+        }
+    }
+}
+
+// Touching a checkpoint moves the player's respawn point to it.
+#[derive(Component)]
+pub struct Checkpoint;
+
+impl Checkpoint {
+    // Everything a checkpoint needs besides its transform and collider.
+    pub fn bundle() -> impl Bundle {
+        (
+            Checkpoint,
+            Sensor,
+            CollidingEntities::default(),
+            CollisionLayers::new(GameLayer::Trigger, [GameLayer::Default]),
+        )
+    }
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub source: Option<Entity>,
+    // Before resistances, criticals and guarding.
+    pub amount: f32,
+    // An id from the `DamageTypeRegistry`.
+    pub damage_type: String,
+    // Passed on as a `KnockbackEvent` if the damage goes through.
+    pub knockback: Vec3,
+    // Damage that is neither blocked by nor grants invulnerability (e.g. damage over time).
+    pub ignores_invulnerability: bool,
+    // Passed on as `ApplyStatusEvent`s if the damage goes through.
+    pub inflicts: Vec<String>,
+}
+
+// Sent for every `DamageEvent` that actually went through (i.e. the target was not invulnerable or
+// already dead), for feedback effects to react to.
+#[derive(Event, Debug, Clone)]
+pub struct DamageDealtEvent {
+    pub target: Entity,
+    pub source: Option<Entity>,
+    pub amount: f32,
+    #[allow(dead_code, reason = "no hit feedback depends on the damage type yet")]
+    pub damage_type: String,
+    #[allow(dead_code, reason = "critical hits get no special feedback yet")]
+    pub critical: bool,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct DeathEvent {
+    pub entity: Entity,
+    pub killer: Option<Entity>,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct LootDropEvent {
+    pub position: Vec3,
+    pub item: String,
+    pub count: u32,
+}
+
+fn tick_invulnerability(time: Res<Time>, mut query: Query<&mut Invulnerability>) {
+    for mut invulnerability in query.iter_mut() {
+        if invulnerability.is_active() {
+            invulnerability.remaining -= time.delta_secs();
+        }
+    }
+}
+
+fn apply_contact_damage(
+    time: Res<Time>,
+    mut hazards_query: Query<(
+        Entity,
+        &GlobalTransform,
+        &Damage,
+        &CollidingEntities,
+        &mut HazardHits,
+    )>,
+    targets_query: Query<&GlobalTransform, With<Health>>,
+    mut damage_writer: EventWriter<DamageEvent>,
+) {
+    let dt = time.delta_secs();
+    for (hazard, hazard_transform, damage, colliding_entities, mut hits) in hazards_query.iter_mut()
+    {
+        hits.0.retain(|_, until_next_hit| {
+            *until_next_hit -= dt;
+            0.0 < *until_next_hit
+        });
+        for &target in colliding_entities.iter() {
+            if hits.0.contains_key(&target) {
+                continue;
+            }
+            let Ok(target_transform) = targets_query.get(target) else {
+                continue;
+            };
+            hits.0.insert(target, damage.interval);
+            // Push the target away from the hazard, and always a bit upwards so that it gets out
+            // of floor hazards.
+            let away = (target_transform.translation() - hazard_transform.translation())
+                .with_y(0.0)
+                .normalize_or_zero();
+            damage_writer.write(DamageEvent {
+                target,
+                source: Some(hazard),
+                amount: damage.amount,
+                damage_type: damage.damage_type.clone(),
+                knockback: (away + Vec3::Y).normalize() * damage.knockback,
+                ignores_invulnerability: false,
+                inflicts: damage.inflicts.clone(),
+            });
+        }
+    }
+}
+
+fn apply_damage(
+    mut damage_reader: EventReader<DamageEvent>,
+    mut query: Query<(
+        &mut Health,
+        Option<&mut Invulnerability>,
+        Option<&Resistances>,
+        Option<(&Guard, &mut GuardState, &mut Stamina, &Facing)>,
+    )>,
+    rolls_query: Query<&DamageRoll>,
+    stats_query: Query<&DerivedStats>,
+    transforms_query: Query<&GlobalTransform>,
+    registry_handle: Res<DamageTypeRegistryHandle>,
+    registries: Res<Assets<DamageTypeRegistry>>,
+    mut knockback_writer: EventWriter<KnockbackEvent>,
+    mut dealt_writer: EventWriter<DamageDealtEvent>,
+    mut death_writer: EventWriter<DeathEvent>,
+    mut status_writer: EventWriter<ApplyStatusEvent>,
+    mut parry_writer: EventWriter<ParryEvent>,
+) {
+    let mut rng = rand::thread_rng();
+    let registry = registries.get(&registry_handle.0);
+    for event in damage_reader.read() {
+        let Ok((mut health, invulnerability, resistances, guard)) = query.get_mut(event.target)
+        else {
+            continue;
+        };
+        if health.is_dead() {
+            continue;
+        }
+        if !event.ignores_invulnerability
+            && invulnerability
+                .as_ref()
+                .is_some_and(|invulnerability| invulnerability.is_active())
+        {
+            continue;
+        }
+
+        // Stats scale the damage before anything else: the attacker's strength, and the target's
+        // defense.
+        let attack = event
+            .source
+            .and_then(|source| stats_query.get(source).ok())
+            .map_or(1.0, |stats| stats.damage_multiplier);
+        let defense = stats_query
+            .get(event.target)
+            .map_or(1.0, |stats| stats.defense_multiplier);
+
+        let roll = event.source.and_then(|source| rolls_query.get(source).ok());
+        let result = calculate_damage(
+            event.amount * attack * defense,
+            &event.damage_type,
+            roll,
+            resistances,
+            &mut rng,
+        );
+        let mut amount = result.amount;
+        let mut knockback = event.knockback;
+        let mut blocked = false;
+        if let Some((guard, mut guard_state, mut stamina, facing)) = guard {
+            let from = hit_direction(event, &transforms_query);
+            match guard_state.resolve_hit(guard, &mut stamina, facing.0, from, amount) {
+                GuardOutcome::Unguarded => {}
+                GuardOutcome::Blocked { multiplier } => {
+                    amount *= multiplier;
+                    knockback *= multiplier;
+                    blocked = true;
+                }
+                GuardOutcome::Parried => {
+                    parry_writer.write(ParryEvent {
+                        defender: event.target,
+                        attacker: event.source,
+                    });
+                    continue;
+                }
+            }
+        }
+        if let (false, Some(mut invulnerability)) = (event.ignores_invulnerability, invulnerability)
+        {
+            invulnerability.remaining = invulnerability.duration;
+        }
+
+        let amount = health.apply_damage(amount);
+        dealt_writer.write(DamageDealtEvent {
+            target: event.target,
+            source: event.source,
+            amount,
+            damage_type: event.damage_type.clone(),
+            critical: result.critical,
+        });
+        if knockback != Vec3::ZERO {
+            knockback_writer.write(KnockbackEvent {
+                entity: event.target,
+                impulse: knockback,
+            });
+        }
+        // Blocking also keeps status effects out.
+        if !blocked {
+            for effect in &event.inflicts {
+                status_writer.write(ApplyStatusEvent {
+                    target: event.target,
+                    effect: effect.clone(),
+                });
+            }
+            // The effects of the damage type only come with hits that have a source - otherwise
+            // e.g. the damage over time of a burn would keep on rekindling it.
+            let damage_type = registry
+                .filter(|_| event.source.is_some())
+                .and_then(|registry| registry.get(&event.damage_type));
+            if let Some(def) = damage_type {
+                for effect in &def.inflicts {
+                    if rng.gen_bool(def.inflict_chance.clamp(0.0, 1.0) as f64) {
+                        status_writer.write(ApplyStatusEvent {
+                            target: event.target,
+                            effect: effect.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        if health.is_dead() {
+            death_writer.write(DeathEvent {
+                entity: event.target,
+                killer: event.source,
+            });
+        }
+    }
+}
+
+// The horizontal direction (-1, 0 or 1) a hit comes from, as seen from its target. The knockback
+// pushes away from where the hit came from, so it's the best indication - otherwise the position
+// of the source is used.
+fn hit_direction(event: &DamageEvent, transforms_query: &Query<&GlobalTransform>) -> f32 {
+    if event.knockback.x != 0.0 {
+        return -event.knockback.x.signum();
+    }
+    let (Some(Ok(source)), Ok(target)) = (
+        event.source.map(|source| transforms_query.get(source)),
+        transforms_query.get(event.target),
+    ) else {
+        return 0.0;
+    };
+    let offset = source.translation().x - target.translation().x;
+    if offset == 0.0 { 0.0 } else { offset.signum() }
+}
+
+// Everything a respawn puts back in order. Besides the position and health, the player comes back
+// out of whatever it was doing or suffering from when it died.
+type PlayerRespawnData = (
+    &'static mut Respawn,
+    &'static mut Health,
+    &'static mut Transform,
+    &'static mut LinearVelocity,
+    Option<&'static mut GravityScale>,
+    Option<&'static mut LedgeState>,
+    Option<&'static mut ClimbState>,
+    Option<&'static mut WallState>,
+    Option<(&'static Swimmer, &'static mut SwimState)>,
+    Option<&'static mut HitStun>,
+    Option<&'static mut StatusEffects>,
+);
+
+fn respawn_player(player: QueryItem<'_, PlayerRespawnData>) {
+    let (
+        respawn,
+        mut health,
+        mut transform,
+        mut velocity,
+        gravity_scale,
+        ledge_state,
+        climb_state,
+        wall_state,
+        swim,
+        hit_stun,
+        status_effects,
+    ) = player;
+    transform.translation = respawn.checkpoint;
+    velocity.0 = Vec3::ZERO;
+    health.restore();
+    if let Some(mut gravity_scale) = gravity_scale {
+        gravity_scale.0 = 1.0;
+    }
+    if let Some(mut ledge_state) = ledge_state {
+        *ledge_state = LedgeState::default();
+    }
+    if let Some(mut climb_state) = climb_state {
+        *climb_state = ClimbState::default();
+    }
+    if let Some(mut wall_state) = wall_state {
+        *wall_state = WallState::default();
+    }
+    if let Some((swimmer, mut swim_state)) = swim {
+        *swim_state = SwimState::new(swimmer);
+    }
+    if let Some(mut hit_stun) = hit_stun {
+        *hit_stun = HitStun::default();
+    }
+    if let Some(mut status_effects) = status_effects {
+        *status_effects = StatusEffects::default();
+    }
+}
+
+fn handle_player_death(
+    mut death_reader: EventReader<DeathEvent>,
+    mut query: Query<PlayerRespawnData, With<Player>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for event in death_reader.read() {
+        let Ok(mut player) = query.get_mut(event.entity) else {
+            continue;
+        };
+        if player.0.lives == 0 {
+            info!("Game over");
+            next_state.set(GameState::GameOver);
+            continue;
+        }
+        player.0.lives -= 1;
+        respawn_player(player);
+    }
+}
+
+// Brings the player back at its last checkpoint with all of its lives.
+fn restart_after_game_over(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut query: Query<PlayerRespawnData, With<Player>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    if let Ok(mut player) = query.single_mut() {
+        player.0.lives = player.0.max_lives;
+        respawn_player(player);
+    }
+    next_state.set(GameState::Ready);
+}
+
+fn show_game_over(mut contexts: EguiContexts) {
+    egui::Area::new(egui::Id::new("game_over"))
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                ui.label(
+                    egui::RichText::new("Game over")
+                        .size(48.0)
+                        .strong()
+                        .color(egui::Color32::RED),
+                );
+                ui.label(egui::RichText::new("Press R to restart").size(20.0));
+            });
+        });
+}
+
+fn handle_death(
+    mut commands: Commands,
+    mut death_reader: EventReader<DeathEvent>,
+    query: Query<(&GlobalTransform, Option<&Loot>), (Without<Player>, Without<DeathSequence>)>,
+    mut loot_writer: EventWriter<LootDropEvent>,
+) {
+    for event in death_reader.read() {
+        let Ok((transform, loot)) = query.get(event.entity) else {
+            continue;
+        };
+        for drop in loot.into_iter().flat_map(|loot| loot.0.iter()) {
+            loot_writer.write(LootDropEvent {
+                position: transform.translation(),
+                item: drop.item.clone(),
+                count: drop.count,
+            });
+        }
+        commands.entity(event.entity).despawn();
+    }
+}
+
+fn update_checkpoints(
+    checkpoints_query: Query<(&GlobalTransform, &CollidingEntities), With<Checkpoint>>,
+    mut player_query: Query<(Entity, &mut Respawn), With<Player>>,
+) {
+    let Ok((player, mut respawn)) = player_query.single_mut() else {
+        return;
+    };
+    for (transform, colliding_entities) in checkpoints_query.iter() {
+        if colliding_entities.contains(&player) {
+            respawn.checkpoint = transform.translation();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    fn damage_is_clamped_at_zero() {
+        let mut health = Health::new(10.0);
+        assert_eq!(health.apply_damage(4.0), 4.0);
+        assert_eq!(health.current, 6.0);
+        // Only what was left is reported as lost.
+        assert_eq!(health.apply_damage(50.0), 6.0);
+        assert_eq!(health.current, 0.0);
+        assert!(health.is_dead());
+        assert_eq!(health.apply_damage(5.0), 0.0);
+        assert_eq!(health.current, 0.0);
+    }
+
+    #[test]
+    fn negative_damage_does_not_heal() {
+        let mut health = Health::new(10.0);
+        health.apply_damage(5.0);
+        assert_eq!(health.apply_damage(-3.0), 0.0);
+        assert_eq!(health.current, 5.0);
+    }
+
+    #[test]
+    fn heal_is_clamped_at_max() {
+        let mut health = Health::new(10.0);
+        health.apply_damage(8.0);
+        health.heal(3.0);
+        assert_eq!(health.current, 5.0);
+        health.heal(100.0);
+        assert_eq!(health.current, 10.0);
+        health.apply_damage(5.0);
+        health.heal(-3.0);
+        assert_eq!(health.current, 5.0);
+    }
+
+    #[test]
+    fn the_dead_cannot_be_healed() {
+        let mut health = Health::new(10.0);
+        health.apply_damage(10.0);
+        health.heal(5.0);
+        assert!(health.is_dead());
+        health.restore();
+        assert_eq!(health.current, 10.0);
+    }
+
+    // Runs `apply_damage` on its own, without a damage type registry.
+    fn damage_world() -> (World, Schedule) {
+        let mut world = World::new();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Events<KnockbackEvent>>();
+        world.init_resource::<Events<DamageDealtEvent>>();
+        world.init_resource::<Events<DeathEvent>>();
+        world.init_resource::<Events<ApplyStatusEvent>>();
+        world.init_resource::<Events<ParryEvent>>();
+        world.init_resource::<Assets<DamageTypeRegistry>>();
+        world.insert_resource(DamageTypeRegistryHandle(Handle::default()));
+        let mut schedule = Schedule::default();
+        schedule.add_systems(apply_damage);
+        (world, schedule)
+    }
+
+    fn hit(world: &mut World, target: Entity, amount: f32) {
+        world.send_event(DamageEvent {
+            target,
+            source: None,
+            amount,
+            damage_type: crate::damage::default_damage_type(),
+            knockback: Vec3::ZERO,
+            ignores_invulnerability: false,
+            inflicts: Vec::new(),
+        });
+    }
+
+    fn deaths(world: &World) -> usize {
+        world.resource::<Events<DeathEvent>>().len()
+    }
+
+    #[test]
+    fn hits_are_ignored_during_invulnerability() {
+        let (mut world, mut schedule) = damage_world();
+        let target = world
+            .spawn((Health::new(100.0), Invulnerability::new(1.0)))
+            .id();
+
+        hit(&mut world, target, 10.0);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Health>(target).unwrap().current, 90.0);
+        assert!(world.get::<Invulnerability>(target).unwrap().is_active());
+
+        hit(&mut world, target, 10.0);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Health>(target).unwrap().current, 90.0);
+
+        // Once the window is over, hits land again.
+        world.get_mut::<Invulnerability>(target).unwrap().remaining = 0.0;
+        hit(&mut world, target, 10.0);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Health>(target).unwrap().current, 80.0);
+    }
+
+    #[test]
+    fn killing_blows_are_reported_once() {
+        let (mut world, mut schedule) = damage_world();
+        let target = world.spawn(Health::new(10.0)).id();
+
+        hit(&mut world, target, 25.0);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Health>(target).unwrap().current, 0.0);
+        assert_eq!(deaths(&world), 1);
+
+        // Hitting the dead neither deals damage nor kills them again.
+        hit(&mut world, target, 5.0);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Health>(target).unwrap().current, 0.0);
+        assert_eq!(deaths(&world), 1);
+    }
+
+    #[test]
+    fn respawning_resets_the_player() {
+        let mut world = World::new();
+        world.init_resource::<Events<DeathEvent>>();
+        world.init_resource::<NextState<GameState>>();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(handle_player_death);
+
+        let ladder = world.spawn_empty().id();
+        let water = world.spawn_empty().id();
+        let swimmer = Swimmer::default();
+        let poison: crate::status::StatusEffectDef =
+            ron::from_str(r#"(id: "poison", duration: 5.0)"#).unwrap();
+        let mut status_effects = StatusEffects::default();
+        status_effects.apply(&poison);
+        let mut ledge_state = LedgeState::default();
+        ledge_state.mode = crate::ledge::LedgeMode::Hanging {
+            ledge: Vec3::ZERO,
+            direction: 1.0,
+        };
+        let mut health = Health::new(50.0);
+        health.apply_damage(50.0);
+        let player = world
+            .spawn((
+                Player,
+                Respawn::new(Vec3::new(4.0, 2.0, 0.0), 1),
+                health,
+                Transform::from_xyz(20.0, -5.0, 0.0),
+                LinearVelocity(Vec3::new(3.0, -10.0, 0.0)),
+                GravityScale(0.0),
+                ledge_state,
+                ClimbState {
+                    climbing: Some(ladder),
+                },
+                WallState {
+                    contact: Some(Dir3::X),
+                    mode: crate::wall::WallMode::Sliding,
+                },
+                SwimState {
+                    water: Some(water),
+                    oxygen: 0.0,
+                    ..SwimState::new(&swimmer)
+                },
+                swimmer,
+                HitStun { remaining: 0.3 },
+                status_effects,
+            ))
+            .id();
+
+        world.send_event(DeathEvent {
+            entity: player,
+            killer: None,
+        });
+        schedule.run(&mut world);
+
+        let entity = world.entity(player);
+        assert_eq!(entity.get::<Respawn>().unwrap().lives, 0);
+        assert_eq!(entity.get::<Health>().unwrap().current, 50.0);
+        assert_eq!(
+            entity.get::<Transform>().unwrap().translation,
+            Vec3::new(4.0, 2.0, 0.0)
+        );
+        assert_eq!(entity.get::<LinearVelocity>().unwrap().0, Vec3::ZERO);
+        assert_eq!(entity.get::<GravityScale>().unwrap().0, 1.0);
+        assert_eq!(
+            entity.get::<LedgeState>().unwrap().mode,
+            crate::ledge::LedgeMode::None
+        );
+        assert_eq!(entity.get::<ClimbState>().unwrap().climbing, None);
+        let wall_state = entity.get::<WallState>().unwrap();
+        assert_eq!(wall_state.contact, None);
+        assert_eq!(wall_state.mode, crate::wall::WallMode::None);
+        let swim_state = entity.get::<SwimState>().unwrap();
+        assert!(!swim_state.is_swimming());
+        assert_eq!(swim_state.oxygen, Swimmer::default().max_oxygen);
+        assert!(!entity.get::<HitStun>().unwrap().is_stunned());
+        assert!(entity.get::<StatusEffects>().unwrap().active.is_empty());
+
+        // Out of lives, the player stays dead and the game is over.
+        world.send_event(DeathEvent {
+            entity: player,
+            killer: None,
+        });
+        schedule.run(&mut world);
+        assert!(matches!(
+            *world.resource::<NextState<GameState>>(),
+            NextState::Pending(GameState::GameOver)
+        ));
+
+        // Restarting gives all the lives back.
+        let mut keyboard = ButtonInput::<KeyCode>::default();
+        keyboard.press(KeyCode::KeyR);
+        world.insert_resource(keyboard);
+        world.run_system_once(restart_after_game_over).unwrap();
+        assert_eq!(world.get::<Respawn>(player).unwrap().lives, 1);
+        assert!(matches!(
+            *world.resource::<NextState<GameState>>(),
+            NextState::Pending(GameState::Ready)
+        ));
+    }
+
+    #[test]
+    fn hazards_hit_each_target_once_per_interval() {
+        let mut world = World::new();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Time>();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(apply_contact_damage);
+
+        let first = world
+            .spawn((Health::new(100.0), GlobalTransform::default()))
+            .id();
+        let second = world
+            .spawn((Health::new(100.0), GlobalTransform::default()))
+            .id();
+        let mut colliding_entities = CollidingEntities::default();
+        colliding_entities.insert(first);
+        let hazard = world
+            .spawn((
+                Damage {
+                    amount: 10.0,
+                    knockback: 0.0,
+                    damage_type: crate::damage::default_damage_type(),
+                    inflicts: Vec::new(),
+                    interval: 1.0,
+                }
+                .hazard_bundle(),
+                GlobalTransform::default(),
+            ))
+            .insert(colliding_entities)
+            .id();
+        let hits_on = |world: &World, target: Entity| {
+            let events = world.resource::<Events<DamageEvent>>();
+            events
+                .iter_current_update_events()
+                .filter(|event| event.target == target)
+                .count()
+        };
+
+        schedule.run(&mut world);
+        assert_eq!(hits_on(&world, first), 1);
+
+        // Staying in contact doesn't hit again before the interval is over...
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(0.6));
+        schedule.run(&mut world);
+        assert_eq!(hits_on(&world, first), 1);
+
+        // ...but another target is hit right away.
+        world.resource_mut::<Time>().advance_by(Duration::ZERO);
+        world
+            .get_mut::<CollidingEntities>(hazard)
+            .unwrap()
+            .insert(second);
+        schedule.run(&mut world);
+        assert_eq!(hits_on(&world, first), 1);
+        assert_eq!(hits_on(&world, second), 1);
+
+        // 1.2s after the first hit.
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(0.6));
+        schedule.run(&mut world);
+        assert_eq!(hits_on(&world, first), 2);
+        assert_eq!(hits_on(&world, second), 1);
+    }
+}
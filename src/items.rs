@@ -0,0 +1,751 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::GameState;
+use crate::health::{Health, LootDropEvent};
+use crate::layers::GameLayer;
+use crate::progression::GainXpEvent;
+use crate::ron_asset::RonAssetLoader;
+use crate::stats::{StatModifier, Stats};
+use crate::status::ApplyStatusEvent;
+
+pub struct ItemsPlugin;
+
+impl Plugin for ItemsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ItemDatabase>()
+            .register_asset_loader(RonAssetLoader::<ItemDatabase>::new(&["items.ron"]))
+            .add_event::<UseItemEvent>()
+            .add_systems(Startup, load_item_database)
+            .add_systems(
+                FixedUpdate,
+                (use_items, spawn_pickups, collect_pickups).run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// All the items of the game, loaded from a `.items.ron` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct ItemDatabase {
+    pub items: Vec<ItemDef>,
+}
+
+impl ItemDatabase {
+    pub fn get(&self, id: &str) -> Option<&ItemDef> {
+        self.items.iter().find(|item| item.id == id)
+    }
+}
+
+#[derive(Resource)]
+pub struct ItemDatabaseHandle(pub Handle<ItemDatabase>);
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ItemDef {
+    pub id: String,
+    pub name: String,
+    #[allow(dead_code, reason = "there's no inventory screen to draw it in yet")]
+    pub icon: ItemIcon,
+    // How many of the item fit in one inventory slot.
+    #[serde(default = "default_stack_size")]
+    pub stack_size: u32,
+    pub category: ItemCategory,
+    // What using the item does. Items without effects can't be used.
+    #[serde(default)]
+    pub effects: Vec<ItemEffect>,
+}
+
+fn default_stack_size() -> u32 {
+    1
+}
+
+// Where the item's icon is: a region (in pixels) of an atlas image.
+#[derive(Deserialize, Clone, Debug)]
+#[allow(dead_code, reason = "no icon is drawn yet")]
+pub struct ItemIcon {
+    pub atlas: String,
+    pub region: URect,
+}
+
+// Also the order `Inventory::sort` puts the items in.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ItemCategory {
+    Consumable,
+    Equipment,
+    Material,
+    Key,
+    Currency,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum ItemEffect {
+    Heal(f32),
+    // The id of a status effect to apply to the user.
+    Status(String),
+    Xp(u32),
+    // A permanent bonus, added to the `Stats` as coming from "item:<id>".
+    Stat(StatModifier),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemStack {
+    pub item: String,
+    pub count: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InventoryError {
+    SlotOutOfRange,
+    EmptySlot,
+    NotEnoughItems,
+    NoFreeSlot,
+    // Shrinking the inventory would leave items outside of it.
+    SlotsInUse,
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SlotOutOfRange => write!(f, "no such slot"),
+            Self::EmptySlot => write!(f, "the slot is empty"),
+            Self::NotEnoughItems => write!(f, "not enough items"),
+            Self::NoFreeSlot => write!(f, "no free slot"),
+            Self::SlotsInUse => write!(f, "the slots to remove are not empty"),
+        }
+    }
+}
+
+impl std::error::Error for InventoryError {}
+
+// A fixed number of slots, each empty or holding a stack of a single item. Operations that need to
+// know how big an item's stacks get take its definition (or the whole database).
+#[derive(Component, Clone, Debug, Default)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+}
+
+#[allow(dead_code, reason = "most of it is for an inventory screen")]
+impl Inventory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots: vec![None; capacity],
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&ItemStack> {
+        self.slots.get(slot)?.as_ref()
+    }
+
+    // The count of an item over all the slots.
+    pub fn count(&self, item: &str) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|stack| stack.item == item)
+            .map(|stack| stack.count)
+            .sum()
+    }
+
+    // Grows the inventory, or shrinks it if the slots to remove are empty.
+    pub fn set_capacity(&mut self, capacity: usize) -> Result<(), InventoryError> {
+        if self.slots.iter().skip(capacity).any(Option::is_some) {
+            return Err(InventoryError::SlotsInUse);
+        }
+        self.slots.resize(capacity, None);
+        Ok(())
+    }
+
+    // Tops up the stacks of the item first, then fills empty slots. Returns how many didn't fit.
+    pub fn add(&mut self, item: &ItemDef, mut count: u32) -> u32 {
+        let stack_size = item.stack_size.max(1);
+        for stack in self.slots.iter_mut().flatten() {
+            if count == 0 {
+                break;
+            }
+            if stack.item == item.id && stack.count < stack_size {
+                let moved = count.min(stack_size - stack.count);
+                stack.count += moved;
+                count -= moved;
+            }
+        }
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if count == 0 {
+                break;
+            }
+            let moved = count.min(stack_size);
+            *slot = Some(ItemStack {
+                item: item.id.clone(),
+                count: moved,
+            });
+            count -= moved;
+        }
+        count
+    }
+
+    // Takes `count` of the item out of the inventory, from the last stacks first. Takes nothing
+    // unless there are enough.
+    pub fn remove(&mut self, item: &str, mut count: u32) -> Result<(), InventoryError> {
+        if self.count(item) < count {
+            return Err(InventoryError::NotEnoughItems);
+        }
+        for slot in self.slots.iter_mut().rev() {
+            if count == 0 {
+                break;
+            }
+            let Some(stack) = slot.as_mut().filter(|stack| stack.item == item) else {
+                continue;
+            };
+            let taken = count.min(stack.count);
+            stack.count -= taken;
+            count -= taken;
+            if stack.count == 0 {
+                *slot = None;
+            }
+        }
+        Ok(())
+    }
+
+    // Takes `count` items out of a slot.
+    pub fn take(&mut self, slot: usize, count: u32) -> Result<ItemStack, InventoryError> {
+        let entry = self
+            .slots
+            .get_mut(slot)
+            .ok_or(InventoryError::SlotOutOfRange)?;
+        let stack = entry.as_mut().ok_or(InventoryError::EmptySlot)?;
+        if stack.count < count {
+            return Err(InventoryError::NotEnoughItems);
+        }
+        stack.count -= count;
+        let taken = ItemStack {
+            item: stack.item.clone(),
+            count,
+        };
+        if stack.count == 0 {
+            *entry = None;
+        }
+        Ok(taken)
+    }
+
+    // Moves `count` items of a slot into a stack of their own, in the first empty slot. Returns
+    // that slot.
+    pub fn split(&mut self, slot: usize, count: u32) -> Result<usize, InventoryError> {
+        let stack = self.get(slot).ok_or(if slot < self.capacity() {
+            InventoryError::EmptySlot
+        } else {
+            InventoryError::SlotOutOfRange
+        })?;
+        // Splitting off nothing or everything would leave an empty stack behind.
+        if count == 0 || stack.count <= count {
+            return Err(InventoryError::NotEnoughItems);
+        }
+        let free = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(InventoryError::NoFreeSlot)?;
+        self.slots[free] = Some(self.take(slot, count)?);
+        Ok(free)
+    }
+
+    // Moves a stack onto another slot. Stacks of the same item are merged as far as they fit, and
+    // different ones swap places.
+    pub fn move_stack(
+        &mut self,
+        from: usize,
+        to: usize,
+        database: &ItemDatabase,
+    ) -> Result<(), InventoryError> {
+        if self.capacity() <= from || self.capacity() <= to {
+            return Err(InventoryError::SlotOutOfRange);
+        }
+        if self.slots[from].is_none() {
+            return Err(InventoryError::EmptySlot);
+        }
+        if from == to {
+            return Ok(());
+        }
+        let (Some(source), Some(target)) = (&self.slots[from], &self.slots[to]) else {
+            self.slots.swap(from, to);
+            return Ok(());
+        };
+        if source.item != target.item {
+            self.slots.swap(from, to);
+            return Ok(());
+        }
+        let stack_size = database.get(&source.item).map_or(1, |def| def.stack_size);
+        let moved = source.count.min(stack_size.saturating_sub(target.count));
+        self.take(from, moved)?;
+        if let Some(target) = self.slots[to].as_mut() {
+            target.count += moved;
+        }
+        Ok(())
+    }
+
+    // Merges the stacks of each item and orders them by category and name, leaving the empty slots
+    // at the end. Items missing from the database go last, as they are.
+    pub fn sort(&mut self, database: &ItemDatabase) {
+        // The total of each item, and how many stacks it was in.
+        let mut totals: Vec<(ItemStack, usize)> = Vec::new();
+        for stack in self.slots.iter_mut().filter_map(Option::take) {
+            let known = database.get(&stack.item).is_some();
+            match totals
+                .iter_mut()
+                .find(|(total, _)| known && total.item == stack.item)
+            {
+                Some((total, stacks)) => {
+                    total.count += stack.count;
+                    *stacks += 1;
+                }
+                None => totals.push((stack, 1)),
+            }
+        }
+        totals.sort_by(|(a, _), (b, _)| {
+            match (database.get(&a.item), database.get(&b.item)) {
+                (Some(a), Some(b)) => (a.category, &a.name).cmp(&(b.category, &b.name)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+            .then_with(|| a.item.cmp(&b.item))
+        });
+
+        // Each item takes at most as many slots as it had stacks, so everything fits back in. Stacks
+        // bigger than the item's stack size (e.g. after it was lowered) stay bigger.
+        let mut slots = self.slots.iter_mut();
+        for (total, stacks) in totals {
+            let stack_size = database
+                .get(&total.item)
+                .map_or(u32::MAX, |def| def.stack_size.max(1));
+            let needed = total.count.div_ceil(stack_size) as usize;
+            let mut count = total.count;
+            let chunks = needed.min(stacks);
+            for (index, slot) in (0..chunks).zip(slots.by_ref()) {
+                let moved = if index + 1 == chunks {
+                    count
+                } else {
+                    count.min(stack_size)
+                };
+                *slot = Some(ItemStack {
+                    item: total.item.clone(),
+                    count: moved,
+                });
+                count -= moved;
+            }
+        }
+    }
+}
+
+// Uses one of the items in a slot of the entity's inventory, applying its effects to the entity.
+#[derive(Event, Debug, Clone)]
+pub struct UseItemEvent {
+    pub entity: Entity,
+    pub slot: usize,
+}
+
+// Dropped items, waiting for an entity with an `Inventory` to touch them. Needs `CollidingEntities` on
+// the same entity.
+#[derive(Component, Clone, Debug)]
+pub struct Pickup {
+    pub item: String,
+    pub count: u32,
+}
+
+impl Pickup {
+    // Everything a pickup needs besides its transform, mesh and collider.
+    pub fn bundle(self) -> impl Bundle {
+        (
+            self,
+            Sensor,
+            CollidingEntities::default(),
+            CollisionLayers::new(GameLayer::Trigger, [GameLayer::Default]),
+        )
+    }
+}
+
+fn load_item_database(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ItemDatabaseHandle(
+        asset_server.load("items/database.items.ron"),
+    ));
+}
+
+fn use_items(
+    database_handle: Res<ItemDatabaseHandle>,
+    databases: Res<Assets<ItemDatabase>>,
+    mut use_reader: EventReader<UseItemEvent>,
+    mut query: Query<(&mut Inventory, Option<&mut Health>, Option<&mut Stats>)>,
+    mut status_writer: EventWriter<ApplyStatusEvent>,
+    mut xp_writer: EventWriter<GainXpEvent>,
+) {
+    let Some(database) = databases.get(&database_handle.0) else {
+        return;
+    };
+    for event in use_reader.read() {
+        let Ok((mut inventory, mut health, mut stats)) = query.get_mut(event.entity) else {
+            continue;
+        };
+        let Some(item) = inventory
+            .get(event.slot)
+            .and_then(|stack| database.get(&stack.item))
+        else {
+            continue;
+        };
+        if item.effects.is_empty() || inventory.take(event.slot, 1).is_err() {
+            continue;
+        }
+        for effect in &item.effects {
+            match effect {
+                ItemEffect::Heal(amount) => {
+                    if let Some(health) = health.as_deref_mut() {
+                        health.heal(*amount);
+                    }
+                }
+                ItemEffect::Status(effect) => {
+                    status_writer.write(ApplyStatusEvent {
+                        target: event.entity,
+                        effect: effect.clone(),
+                    });
+                }
+                ItemEffect::Xp(amount) => {
+                    xp_writer.write(GainXpEvent {
+                        entity: event.entity,
+                        amount: *amount,
+                    });
+                }
+                ItemEffect::Stat(modifier) => {
+                    if let Some(stats) = stats.as_deref_mut() {
+                        stats.add_modifier(format!("item:{}", item.id), *modifier);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn spawn_pickups(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut loot_reader: EventReader<LootDropEvent>,
+) {
+    for event in loot_reader.read() {
+        commands.spawn((
+            Mesh3d(meshes.add(Cuboid::from_length(0.4))),
+            MeshMaterial3d(materials.add(Color::srgb(0.9, 0.75, 0.2))),
+            Transform::from_translation(event.position),
+            // Bigger than the cube, so that it's easy to grab.
+            Collider::cuboid(0.8, 0.8, 0.8),
+            Pickup {
+                item: event.item.clone(),
+                count: event.count,
+            }
+            .bundle(),
+        ));
+    }
+}
+
+// Puts the pickups in the inventory of the entities touching them. Whatever doesn't fit stays on the
+// ground.
+fn collect_pickups(
+    mut commands: Commands,
+    database_handle: Res<ItemDatabaseHandle>,
+    databases: Res<Assets<ItemDatabase>>,
+    mut pickups_query: Query<(Entity, &mut Pickup, &CollidingEntities)>,
+    mut inventories_query: Query<&mut Inventory>,
+) {
+    let Some(database) = databases.get(&database_handle.0) else {
+        return;
+    };
+    for (entity, mut pickup, colliding_entities) in pickups_query.iter_mut() {
+        let Some(item) = database.get(&pickup.item) else {
+            warn!("Unknown item {}", pickup.item);
+            commands.entity(entity).despawn();
+            continue;
+        };
+        for &collector in colliding_entities.iter() {
+            if let Ok(mut inventory) = inventories_query.get_mut(collector) {
+                pickup.count = inventory.add(item, pickup.count);
+            }
+        }
+        if pickup.count == 0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, name: &str, category: ItemCategory, stack_size: u32) -> ItemDef {
+        ItemDef {
+            id: id.to_string(),
+            name: name.to_string(),
+            icon: ItemIcon {
+                atlas: "items.png".to_string(),
+                region: URect::new(0, 0, 16, 16),
+            },
+            stack_size,
+            category,
+            effects: Vec::new(),
+        }
+    }
+
+    fn database() -> ItemDatabase {
+        ItemDatabase {
+            items: vec![
+                item("potion", "Potion", ItemCategory::Consumable, 10),
+                item("sword", "Sword", ItemCategory::Equipment, 1),
+                item("coin", "Coin", ItemCategory::Currency, 99),
+                item("antidote", "Antidote", ItemCategory::Consumable, 10),
+            ],
+        }
+    }
+
+    fn stack(item: &str, count: u32) -> Option<ItemStack> {
+        Some(ItemStack {
+            item: item.to_string(),
+            count,
+        })
+    }
+
+    #[test]
+    fn adding_fills_stacks_up_to_their_size() {
+        let database = database();
+        let potion = database.get("potion").unwrap();
+        let mut inventory = Inventory::new(4);
+        assert_eq!(inventory.add(potion, 4), 0);
+        assert_eq!(inventory.add(potion, 8), 0);
+        // The first stack is topped up before a new one is started.
+        assert_eq!(
+            inventory.slots(),
+            [stack("potion", 10), stack("potion", 2), None, None]
+        );
+        assert_eq!(inventory.count("potion"), 12);
+    }
+
+    #[test]
+    fn adding_returns_what_does_not_fit() {
+        let database = database();
+        let mut inventory = Inventory::new(2);
+        assert_eq!(inventory.add(database.get("sword").unwrap(), 1), 0);
+        assert_eq!(inventory.add(database.get("potion").unwrap(), 15), 5);
+        assert_eq!(inventory.slots(), [stack("sword", 1), stack("potion", 10)]);
+        assert_eq!(inventory.add(database.get("sword").unwrap(), 1), 1);
+    }
+
+    #[test]
+    fn removing_takes_all_or_nothing() {
+        let database = database();
+        let mut inventory = Inventory::new(3);
+        inventory.add(database.get("potion").unwrap(), 14);
+        assert_eq!(
+            inventory.remove("potion", 15),
+            Err(InventoryError::NotEnoughItems)
+        );
+        assert_eq!(inventory.count("potion"), 14);
+        // From the last stacks first.
+        assert_eq!(inventory.remove("potion", 6), Ok(()));
+        assert_eq!(inventory.slots(), [stack("potion", 8), None, None]);
+    }
+
+    #[test]
+    fn splitting_moves_items_into_an_empty_slot() {
+        let database = database();
+        let mut inventory = Inventory::new(3);
+        inventory.add(database.get("sword").unwrap(), 1);
+        inventory.add(database.get("potion").unwrap(), 6);
+        assert_eq!(inventory.split(1, 2), Ok(2));
+        assert_eq!(
+            inventory.slots(),
+            [stack("sword", 1), stack("potion", 4), stack("potion", 2)]
+        );
+    }
+
+    #[test]
+    fn splitting_fails_without_a_free_slot() {
+        let database = database();
+        let mut inventory = Inventory::new(2);
+        inventory.add(database.get("sword").unwrap(), 1);
+        inventory.add(database.get("potion").unwrap(), 6);
+        assert_eq!(inventory.split(1, 2), Err(InventoryError::NoFreeSlot));
+        assert_eq!(inventory.slots(), [stack("sword", 1), stack("potion", 6)]);
+    }
+
+    #[test]
+    fn splitting_needs_something_to_leave_behind() {
+        let database = database();
+        let mut inventory = Inventory::new(3);
+        inventory.add(database.get("potion").unwrap(), 6);
+        assert_eq!(inventory.split(0, 0), Err(InventoryError::NotEnoughItems));
+        assert_eq!(inventory.split(0, 6), Err(InventoryError::NotEnoughItems));
+        assert_eq!(inventory.split(1, 1), Err(InventoryError::EmptySlot));
+        assert_eq!(inventory.split(3, 1), Err(InventoryError::SlotOutOfRange));
+    }
+
+    #[test]
+    fn moving_onto_another_item_swaps() {
+        let database = database();
+        let mut inventory = Inventory::new(3);
+        inventory.add(database.get("sword").unwrap(), 1);
+        inventory.add(database.get("potion").unwrap(), 3);
+        assert_eq!(inventory.move_stack(0, 1, &database), Ok(()));
+        assert_eq!(
+            inventory.slots(),
+            [stack("potion", 3), stack("sword", 1), None]
+        );
+        // Onto an empty slot.
+        assert_eq!(inventory.move_stack(1, 2, &database), Ok(()));
+        assert_eq!(
+            inventory.slots(),
+            [stack("potion", 3), None, stack("sword", 1)]
+        );
+        assert_eq!(
+            inventory.move_stack(1, 0, &database),
+            Err(InventoryError::EmptySlot)
+        );
+    }
+
+    #[test]
+    fn moving_onto_the_same_item_merges() {
+        let database = database();
+        let mut inventory = Inventory::new(3);
+        inventory.add(database.get("potion").unwrap(), 6);
+        inventory.split(0, 3).unwrap();
+        assert_eq!(inventory.move_stack(1, 0, &database), Ok(()));
+        assert_eq!(inventory.slots(), [stack("potion", 6), None, None]);
+
+        // Only as many as fit move, the rest stays behind.
+        inventory.add(database.get("potion").unwrap(), 8);
+        assert_eq!(
+            inventory.slots(),
+            [stack("potion", 10), stack("potion", 4), None]
+        );
+        inventory.take(0, 3).unwrap();
+        assert_eq!(inventory.move_stack(1, 0, &database), Ok(()));
+        assert_eq!(
+            inventory.slots(),
+            [stack("potion", 10), stack("potion", 1), None]
+        );
+    }
+
+    #[test]
+    fn sorting_merges_and_orders_by_category_and_name() {
+        let database = database();
+        let mut inventory = Inventory::new(7);
+        inventory.add(database.get("coin").unwrap(), 5);
+        inventory.add(database.get("sword").unwrap(), 1);
+        inventory.add(database.get("potion").unwrap(), 3);
+        inventory.split(2, 1).unwrap();
+        inventory.add(database.get("antidote").unwrap(), 2);
+        inventory.slots[6] = stack("mystery", 1);
+        inventory.sort(&database);
+        assert_eq!(
+            inventory.slots(),
+            [
+                stack("antidote", 2),
+                stack("potion", 3),
+                stack("sword", 1),
+                stack("coin", 5),
+                // Unknown items go last.
+                stack("mystery", 1),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn sorting_keeps_unknown_and_oversized_stacks_whole() {
+        let database = database();
+        let mut inventory = Inventory::new(1);
+        inventory.slots[0] = stack("mystery", 3);
+        inventory.sort(&database);
+        assert_eq!(inventory.slots(), [stack("mystery", 3)]);
+
+        let mut inventory = Inventory::new(4);
+        inventory.slots[0] = stack("mystery", 2);
+        inventory.slots[1] = stack("potion", 15);
+        inventory.slots[2] = stack("mystery", 1);
+        inventory.slots[3] = stack("potion", 3);
+        inventory.sort(&database);
+        assert_eq!(
+            inventory.slots(),
+            [
+                stack("potion", 10),
+                stack("potion", 8),
+                stack("mystery", 2),
+                stack("mystery", 1),
+            ]
+        );
+
+        let mut inventory = Inventory::new(2);
+        inventory.slots[1] = stack("potion", 25);
+        inventory.sort(&database);
+        assert_eq!(inventory.slots(), [stack("potion", 25), None]);
+    }
+
+    #[test]
+    fn shrinking_keeps_occupied_slots() {
+        let database = database();
+        let mut inventory = Inventory::new(4);
+        inventory.add(database.get("sword").unwrap(), 1);
+        inventory.add(database.get("potion").unwrap(), 1);
+        inventory.take(0, 1).unwrap();
+        assert_eq!(inventory.set_capacity(1), Err(InventoryError::SlotsInUse));
+        assert_eq!(inventory.capacity(), 4);
+        assert_eq!(inventory.set_capacity(2), Ok(()));
+        assert_eq!(inventory.slots(), [None, stack("potion", 1)]);
+        assert_eq!(inventory.set_capacity(3), Ok(()));
+        assert_eq!(inventory.capacity(), 3);
+    }
+
+    #[test]
+    fn pickups_leave_behind_what_does_not_fit() {
+        let mut world = World::new();
+        world.init_resource::<Assets<ItemDatabase>>();
+        let handle = world.resource_mut::<Assets<ItemDatabase>>().add(database());
+        world.insert_resource(ItemDatabaseHandle(handle));
+        let mut schedule = Schedule::default();
+        schedule.add_systems(collect_pickups);
+
+        let collector = world.spawn(Inventory::new(1)).id();
+        let pickup = world
+            .spawn(Pickup {
+                item: "potion".to_string(),
+                count: 15,
+            })
+            .insert(CollidingEntities([collector].into_iter().collect()))
+            .id();
+
+        schedule.run(&mut world);
+        assert_eq!(
+            world.get::<Inventory>(collector).unwrap().slots(),
+            [stack("potion", 10)]
+        );
+        assert_eq!(world.get::<Pickup>(pickup).unwrap().count, 5);
+
+        // Once there's room, the rest is picked up too.
+        world
+            .get_mut::<Inventory>(collector)
+            .unwrap()
+            .take(0, 10)
+            .unwrap();
+        schedule.run(&mut world);
+        assert_eq!(
+            world.get::<Inventory>(collector).unwrap().slots(),
+            [stack("potion", 5)]
+        );
+        assert!(world.get_entity(pickup).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+use crate::GameState;
+use crate::health::DamageDealtEvent;
+use crate::knockback::KnockbackSystems;
+use crate::melee::AttackState;
+
+pub struct JuicePlugin;
+
+impl Plugin for JuicePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HitFeedback>()
+            .add_systems(
+                FixedUpdate,
+                (tick_hitstop, start_hit_feedback)
+                    .chain()
+                    // Starting the hitstop after the knockback was applied makes the knockback
+                    // velocity part of what gets frozen and restored.
+                    .after(KnockbackSystems)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(Update, (shake_camera, tick_hit_flash));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct HitFeedback {
+    // How long (in seconds) the entities involved in a melee hit freeze.
+    pub hitstop_duration: f32,
+    pub flash_duration: f32,
+    // Multiplies the material's color while flashing. Values above 1 push the sprite to white.
+    pub flash_intensity: f32,
+    // Camera trauma added per point of damage. Trauma is capped at 1.
+    pub trauma_per_damage: f32,
+}
+
+impl Default for HitFeedback {
+    fn default() -> Self {
+        Self {
+            hitstop_duration: 0.08,
+            flash_duration: 0.1,
+            flash_intensity: 8.0,
+            trauma_per_damage: 0.03,
+        }
+    }
+}
+
+// Freezes an entity's physics and animation for a moment.
+#[derive(Component, Debug)]
+pub struct Hitstop {
+    pub remaining: f32,
+    saved_velocity: Vec3,
+}
+
+// Trauma-based screen shake. The shake strength is the square of the trauma, which decays over
+// time, so small hits barely move the camera while big ones shake it hard.
+#[derive(Component, Debug)]
+pub struct CameraShake {
+    pub trauma: f32,
+    // Trauma lost per second.
+    pub decay: f32,
+    pub max_offset: f32,
+    // In radians.
+    pub max_roll: f32,
+    base: Option<Transform>,
+    elapsed: f32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        Self {
+            trauma: 0.0,
+            decay: 1.5,
+            max_offset: 0.6,
+            max_roll: 0.05,
+            base: None,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl CameraShake {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    // The transform the camera shakes around. Moving the camera means moving this, as the shake
+    // overwrites the transform itself.
+    pub fn base(&self) -> Option<Transform> {
+        self.base
+    }
+
+    pub fn set_base(&mut self, base: Transform) {
+        self.base = Some(base);
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct HitFlash {
+    pub remaining: f32,
+    original: Handle<StandardMaterial>,
+}
+
+fn start_hit_feedback(
+    mut commands: Commands,
+    feedback: Res<HitFeedback>,
+    mut dealt_reader: EventReader<DamageDealtEvent>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    bodies_query: Query<(Option<&LinearVelocity>, Option<&AttackState>), Without<Hitstop>>,
+    mut flash_query: Query<&mut MeshMaterial3d<StandardMaterial>, Without<HitFlash>>,
+    mut cameras_query: Query<&mut CameraShake>,
+) {
+    for event in dealt_reader.read() {
+        // Hitstop is for melee hits - the attacker is only frozen if it's in the middle of an
+        // attack (and not, say, the owner of a projectile that hit far away).
+        let attacker = event.source.filter(|source| {
+            bodies_query
+                .get(*source)
+                .is_ok_and(|(_, attack)| attack.is_some_and(|attack| attack.active.is_some()))
+        });
+        for entity in std::iter::once(event.target).chain(attacker) {
+            let Ok((velocity, _)) = bodies_query.get(entity) else {
+                continue;
+            };
+            commands.entity(entity).insert((
+                Hitstop {
+                    remaining: feedback.hitstop_duration,
+                    saved_velocity: velocity.map_or(Vec3::ZERO, |velocity| velocity.0),
+                },
+                RigidBodyDisabled,
+            ));
+        }
+
+        if let Ok(mut material) = flash_query.get_mut(event.target) {
+            // Sprites with the same image share their material, so the flash needs a copy.
+            if let Some(mut flash) = materials.get(&material.0).cloned() {
+                flash.base_color = (flash.base_color.to_linear() * feedback.flash_intensity).into();
+                let original = std::mem::replace(&mut material.0, materials.add(flash));
+                commands.entity(event.target).insert(HitFlash {
+                    remaining: feedback.flash_duration,
+                    original,
+                });
+            }
+        }
+
+        for mut shake in cameras_query.iter_mut() {
+            shake.add_trauma(event.amount * feedback.trauma_per_damage);
+        }
+    }
+}
+
+fn tick_hitstop(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Hitstop, Option<&mut LinearVelocity>)>,
+) {
+    for (entity, mut hitstop, velocity) in query.iter_mut() {
+        hitstop.remaining -= time.delta_secs();
+        if 0.0 < hitstop.remaining {
+            continue;
+        }
+        if let Some(mut velocity) = velocity {
+            velocity.0 = hitstop.saved_velocity;
+        }
+        commands
+            .entity(entity)
+            .remove::<(Hitstop, RigidBodyDisabled)>();
+    }
+}
+
+fn tick_hit_flash(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut HitFlash, &mut MeshMaterial3d<StandardMaterial>)>,
+) {
+    for (entity, mut flash, mut material) in query.iter_mut() {
+        flash.remaining -= time.delta_secs();
+        if flash.remaining <= 0.0 {
+            material.0 = flash.original.clone();
+            commands.entity(entity).remove::<HitFlash>();
+        }
+    }
+}
+
+fn shake_camera(time: Res<Time>, mut query: Query<(&mut CameraShake, &mut Transform)>) {
+    for (mut shake, mut transform) in query.iter_mut() {
+        let base = *shake.base.get_or_insert(*transform);
+        shake.elapsed += time.delta_secs();
+        shake.trauma = (shake.trauma - shake.decay * time.delta_secs()).max(0.0);
+
+        // Sines with unrelated frequencies are a cheap stand-in for noise.
+        let strength = shake.trauma * shake.trauma;
+        let t = shake.elapsed;
+        let offset = Vec3::new((t * 37.0).sin(), (t * 53.0 + 1.3).sin(), 0.0);
+        *transform = base;
+        transform.translation += offset * shake.max_offset * strength;
+        transform.rotate_local_z((t * 29.0 + 2.7).sin() * shake.max_roll * strength);
+    }
+}
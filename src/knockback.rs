@@ -0,0 +1,126 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+
+use crate::GameState;
+use crate::climb::ClimbState;
+use crate::health::HealthSystems;
+use crate::ledge::{LedgeMode, LedgeState};
+
+pub struct KnockbackPlugin;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KnockbackSystems;
+
+impl Plugin for KnockbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<KnockbackEvent>().add_systems(
+            FixedUpdate,
+            (tick_hit_stun, apply_knockback)
+                .chain()
+                .in_set(KnockbackSystems)
+                // Damage is what usually causes knockback, so react to it in the same tick.
+                .after(HealthSystems)
+                .before(TnuaUserControlsSystemSet)
+                .run_if(in_state(GameState::Ready)),
+        );
+    }
+}
+
+// Send this to push a character away (e.g. when it gets hit).
+#[derive(Event, Debug, Clone)]
+pub struct KnockbackEvent {
+    pub entity: Entity,
+    pub impulse: Vec3,
+}
+
+// Makes a character react to `KnockbackEvent`s.
+#[derive(Component, Clone, Debug)]
+pub struct Knockback {
+    // Scales incoming impulses. 0 makes the character immune to knockback.
+    pub impulse_scale: f32,
+    // For how long (in seconds) the character loses control after being knocked back.
+    pub stun_duration: f32,
+    // The acceleration of the walk basis while stunned. Keeping it low lets the knockback play out
+    // instead of Tnua immediately braking the character to a halt.
+    pub stunned_acceleration: f32,
+}
+
+impl Default for Knockback {
+    fn default() -> Self {
+        Self {
+            impulse_scale: 1.0,
+            stun_duration: 0.4,
+            stunned_acceleration: 5.0,
+        }
+    }
+}
+
+#[derive(Component, Default, Debug)]
+pub struct HitStun {
+    // Seconds left until the character regains control.
+    pub remaining: f32,
+}
+
+impl HitStun {
+    pub fn is_stunned(&self) -> bool {
+        0.0 < self.remaining
+    }
+}
+
+fn tick_hit_stun(time: Res<Time>, mut query: Query<&mut HitStun>) {
+    for mut hit_stun in query.iter_mut() {
+        if hit_stun.is_stunned() {
+            hit_stun.remaining -= time.delta_secs();
+        }
+    }
+}
+
+fn apply_knockback(
+    mut knockback_reader: EventReader<KnockbackEvent>,
+    mut query: Query<(
+        &Knockback,
+        &mut HitStun,
+        &mut LinearVelocity,
+        &ComputedMass,
+        Option<&mut GravityScale>,
+        Option<&mut ClimbState>,
+        Option<&mut LedgeState>,
+    )>,
+) {
+    for event in knockback_reader.read() {
+        let Ok((
+            knockback,
+            mut hit_stun,
+            mut velocity,
+            mass,
+            gravity_scale,
+            climb_state,
+            ledge_state,
+        )) = query.get_mut(event.entity)
+        else {
+            continue;
+        };
+        if knockback.impulse_scale <= 0.0 {
+            continue;
+        }
+
+        // Getting hit knocks the character off ladders and ledges.
+        let mut was_hanging = false;
+        if let Some(mut climb_state) = climb_state {
+            was_hanging |= climb_state.climbing.take().is_some();
+        }
+        if let Some(mut ledge_state) = ledge_state {
+            was_hanging |= ledge_state.mode != LedgeMode::None;
+            ledge_state.mode = LedgeMode::None;
+        }
+        if let (true, Some(mut gravity_scale)) = (was_hanging, gravity_scale) {
+            gravity_scale.0 = 1.0;
+        }
+
+        // Apply the impulse to the velocity right away (rather than with `ExternalImpulse`) so that
+        // systems running after this one in the same tick already see the knockback.
+        velocity.0 += event.impulse * knockback.impulse_scale * mass.inverse();
+        hit_stun.remaining = knockback.stun_duration;
+    }
+}
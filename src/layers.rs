@@ -0,0 +1,16 @@
+use avian3d::prelude::*;
+
+// Collision layers, used to tell the different kinds of colliders apart in collision filtering and
+// in spatial queries.
+#[derive(PhysicsLayer, Clone, Copy, Debug, Default)]
+pub enum GameLayer {
+    // Level geometry and characters.
+    #[default]
+    Default,
+    // Sensor volumes (like ladders) that must not be mistaken for solid geometry.
+    Trigger,
+    // The colliders of attacks, which only interact with hurtboxes.
+    Hitbox,
+    // The parts of characters that can be hit.
+    Hurtbox,
+}
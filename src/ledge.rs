@@ -0,0 +1,251 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+
+use crate::abilities::MovementAbilities;
+use crate::climb::ClimbState;
+use crate::controls::KeyPresses;
+use crate::layers::GameLayer;
+use crate::{FLOAT_HEIGHT, GameState, Player};
+
+pub struct LedgePlugin;
+
+impl Plugin for LedgePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            update_ledge_grab
+                .before(TnuaUserControlsSystemSet)
+                .run_if(in_state(GameState::Ready)),
+        );
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct LedgeGrab {
+    // How far in front of the character's center a ledge can be grabbed.
+    pub reach: f32,
+    // How far above the character's center the top of a ledge can be grabbed.
+    pub grab_height: f32,
+    // How far below the top of the ledge the character's center is while hanging.
+    pub hang_offset: f32,
+    // The horizontal distance between the character's center and the wall while hanging.
+    pub hang_distance: f32,
+    // How long (in seconds) it takes to climb up from hanging to standing on the ledge.
+    pub mantle_duration: f32,
+    // How long (in seconds) after letting go of a ledge before it can be grabbed again.
+    pub regrab_cooldown: f32,
+}
+
+impl Default for LedgeGrab {
+    fn default() -> Self {
+        Self {
+            reach: 1.0,
+            grab_height: 1.5,
+            hang_offset: 0.8,
+            hang_distance: 0.55,
+            mantle_duration: 0.4,
+            regrab_cooldown: 0.3,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum LedgeMode {
+    #[default]
+    None,
+    // Hanging from the ledge whose top corner is at `ledge`, on the side opposite to `direction`.
+    Hanging {
+        ledge: Vec3,
+        direction: f32,
+    },
+    // Climbing up onto the ledge.
+    Mantling {
+        ledge: Vec3,
+        direction: f32,
+        elapsed: f32,
+    },
+}
+
+#[derive(Component, Default, Debug)]
+pub struct LedgeState {
+    pub mode: LedgeMode,
+    cooldown: f32,
+}
+
+impl LedgeState {
+    pub fn is_active(&self) -> bool {
+        self.mode != LedgeMode::None
+    }
+}
+
+fn update_ledge_grab(
+    time: Res<Time>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    presses: Res<KeyPresses>,
+    spatial_query: SpatialQuery,
+    mut query: Query<
+        (
+            Entity,
+            &Transform,
+            &TnuaController,
+            &MovementAbilities,
+            &LedgeGrab,
+            &mut LedgeState,
+            &mut LinearVelocity,
+            &mut GravityScale,
+            Option<&ClimbState>,
+        ),
+        With<Player>,
+    >,
+) {
+    let Ok((
+        entity,
+        transform,
+        controller,
+        abilities,
+        grab,
+        mut state,
+        mut velocity,
+        mut gravity_scale,
+        climb_state,
+    )) = query.single_mut()
+    else {
+        return;
+    };
+    let position = transform.translation;
+    state.cooldown -= time.delta_secs();
+
+    match state.mode {
+        LedgeMode::None => {}
+        LedgeMode::Hanging { ledge, direction } => {
+            if presses.just_pressed(KeyCode::ArrowUp) || presses.just_pressed(KeyCode::Space) {
+                state.mode = LedgeMode::Mantling {
+                    ledge,
+                    direction,
+                    elapsed: 0.0,
+                };
+            } else if presses.just_pressed(KeyCode::ArrowDown) {
+                state.mode = LedgeMode::None;
+                state.cooldown = grab.regrab_cooldown;
+                gravity_scale.0 = 1.0;
+            } else {
+                velocity.0 = Vec3::ZERO;
+            }
+            return;
+        }
+        LedgeMode::Mantling {
+            ledge,
+            direction,
+            elapsed,
+        } => {
+            let elapsed = elapsed + time.delta_secs();
+            let half = 0.5 * grab.mantle_duration;
+            // The mantle is done in two straight moves - first up along the wall until the body
+            // clears the ledge, then forward onto it.
+            let (target, time_left) = if elapsed < half {
+                (
+                    Vec3::new(position.x, ledge.y + grab.hang_offset + 0.1, position.z),
+                    half - elapsed,
+                )
+            } else {
+                (
+                    Vec3::new(
+                        ledge.x + direction * grab.hang_distance,
+                        ledge.y + FLOAT_HEIGHT,
+                        position.z,
+                    ),
+                    grab.mantle_duration - elapsed,
+                )
+            };
+            if time_left <= 0.0 {
+                state.mode = LedgeMode::None;
+                gravity_scale.0 = 1.0;
+                velocity.0 = Vec3::ZERO;
+            } else {
+                state.mode = LedgeMode::Mantling {
+                    ledge,
+                    direction,
+                    elapsed,
+                };
+                velocity.0 = (target - position) / time_left.max(time.delta_secs());
+            }
+            return;
+        }
+    }
+
+    let falling = velocity.y < 0.0
+        && controller
+            .concrete_basis::<TnuaBuiltinWalk>()
+            .is_some_and(|(_, basis_state)| basis_state.standing_on_entity().is_none());
+    let climbing = climb_state.is_some_and(|climb| climb.climbing.is_some());
+    if !abilities.ledge_grab || !falling || climbing || 0.0 < state.cooldown {
+        return;
+    }
+
+    // The character only grabs ledges it is moving towards.
+    let direction = match (
+        keyboard.pressed(KeyCode::ArrowLeft),
+        keyboard.pressed(KeyCode::ArrowRight),
+    ) {
+        (true, false) => -1.0,
+        (false, true) => 1.0,
+        _ => return,
+    };
+    let Some(ledge) = find_ledge(&spatial_query, entity, position, direction, grab) else {
+        return;
+    };
+
+    state.mode = LedgeMode::Hanging { ledge, direction };
+    gravity_scale.0 = 0.0;
+    // Move into the hanging position during the next physics step. From then on the `Hanging`
+    // branch keeps the character in place.
+    let hang_position = Vec3::new(
+        ledge.x - direction * grab.hang_distance,
+        ledge.y - grab.hang_offset,
+        position.z,
+    );
+    velocity.0 = (hang_position - position) / time.delta_secs();
+}
+
+// A ledge is a wall in front of the character with free space above its top, and the top being
+// within reach. Returns the top corner of the ledge.
+fn find_ledge(
+    spatial_query: &SpatialQuery,
+    entity: Entity,
+    position: Vec3,
+    direction: f32,
+    grab: &LedgeGrab,
+) -> Option<Vec3> {
+    let filter = SpatialQueryFilter::from_excluded_entities([entity]).with_mask(GameLayer::Default);
+    let forward = if 0.0 < direction {
+        Dir3::X
+    } else {
+        Dir3::NEG_X
+    };
+
+    let wall = spatial_query.cast_ray(position, forward, grab.reach, true, &filter)?;
+    if 0.3 < wall.normal.y.abs() {
+        return None;
+    }
+
+    let above = position + Vec3::Y * grab.grab_height;
+    if spatial_query
+        .cast_ray(above, forward, grab.reach, true, &filter)
+        .is_some()
+    {
+        return None;
+    }
+
+    // Probe down from above, just past the face of the wall, to find the top of the ledge.
+    let probe = above + forward * (wall.distance + 0.1);
+    let top = spatial_query.cast_ray(probe, Dir3::NEG_Y, grab.grab_height, true, &filter)?;
+    if top.normal.y < 0.7 {
+        return None;
+    }
+    Some(Vec3::new(
+        position.x + direction * wall.distance,
+        probe.y - top.distance,
+        position.z,
+    ))
+}
@@ -1,14 +1,79 @@
+mod abilities;
+mod behavior;
+mod boss;
+mod climb;
+mod combo;
+mod controls;
+mod crouch;
+mod damage;
+mod encounter;
+mod enemy;
+mod flying;
+mod guard;
+mod health;
+mod items;
+mod juice;
+mod knockback;
+mod layers;
+mod ledge;
+mod melee;
+mod navigation;
+mod perception;
+mod progression;
+mod projectile;
+mod ron_asset;
+mod skills;
+mod stats;
+mod status;
+mod wall;
+mod water;
+
 use bevy::prelude::*;
 use bevy_sprite3d::prelude::*;
 use avian3d::prelude::*;
 
 use bevy_tnua::{
-    builtins::TnuaBuiltinJumpState, prelude::*, TnuaAnimatingState, TnuaAnimatingStateDirective,
+    builtins::{TnuaBuiltinCrouch, TnuaBuiltinDash, TnuaBuiltinJumpState},
+    control_helpers::{TnuaCrouchEnforcer, TnuaSimpleAirActionsCounter},
+    prelude::*,
+    TnuaAnimatingState,
 };
 use bevy_tnua_avian3d::*;
+use bevy_egui::EguiPlugin;
+use serde::Deserialize;
+
+use abilities::MovementAbilities;
+use behavior::BehaviorPlugin;
+use boss::{Boss, BossPlugin};
+use climb::{ClimbMovement, ClimbPlugin, ClimbState, Climbable};
+use combo::{ComboPlugin, UnlockedMoves};
+use controls::{ControlsPlugin, KeyPresses};
+use crouch::{CrouchMovement, CrouchPlugin, CrouchState};
+use damage::{DamagePlugin, DamageRoll, Resistances, default_damage_type};
+use encounter::{Arena, ArenaGate, EncounterPlugin, Spawner};
+use enemy::{Enemy, EnemyPlugin, PatrolRoute};
+use flying::FlyingPlugin;
+use guard::{Guard, GuardPlugin, GuardState, Stamina};
+use health::{
+    Checkpoint, Damage, Health, HealthPlugin, Invulnerability, Loot, LootDrop, Respawn,
+};
+use items::{Inventory, ItemsPlugin};
+use juice::{CameraShake, Hitstop, JuicePlugin};
+use knockback::{HitStun, Knockback, KnockbackPlugin};
+use ledge::{LedgeGrab, LedgeMode, LedgePlugin, LedgeState};
+use melee::{AttackInput, AttackState, Hurtbox, MeleeAttack, MeleeAttacker, MeleePlugin};
+use navigation::NavigationPlugin;
+use perception::{NoiseMaker, PerceptionPlugin};
+use progression::{CharacterClass, Experience, ProgressionPlugin};
+use projectile::{ProjectileDef, ProjectileLauncher, ProjectilePlugin};
+use skills::{SkillSet, SkillsPlugin};
+use stats::{DerivedStats, Stats, StatsPlugin};
+use status::{StatusEffects, StatusPlugin};
+use wall::{WallMode, WallMovement, WallPlugin, WallState};
+use water::{SwimState, Swimmer, Water, WaterPlugin};
 
 #[derive(States, Hash, Clone, PartialEq, Eq, Debug, Default)]
-enum GameState { #[default] Loading, Ready }
+enum GameState { #[default] Loading, Ready, GameOver }
 
 // #[derive(Component, PartialEq, Eq)]
 // enum PlayerState {
@@ -19,6 +84,20 @@ enum GameState { #[default] Loading, Ready }
 #[derive(Component)]
 struct Player;
 
+// The horizontal direction a character faces: 1.0 for right, -1.0 for left.
+#[derive(Component, Clone, Copy, Debug)]
+struct Facing(f32);
+
+// How fast the player walks when nothing slows it down or speeds it up, and how high it jumps -
+// unless its stats say otherwise.
+const WALK_SPEED: f32 = 10.0;
+const JUMP_HEIGHT: f32 = 4.0;
+// How far and how fast the player dashes, once the dash is unlocked.
+const DASH_DISTANCE: f32 = 5.0;
+const DASH_SPEED: f32 = 30.0;
+// The `float_height` of the player's walk basis.
+const FLOAT_HEIGHT: f32 = 2.0;
+
 #[derive(Resource, Default)]
 struct ImageAssets {
     image: Handle<Image>,               // the `image` field here is only used to query the load state, lots of the
@@ -65,13 +144,59 @@ fn main() {
             PhysicsPlugins::default(),
             TnuaControllerPlugin::new(FixedUpdate),
             TnuaAvian3dPlugin::new(FixedUpdate),
+            EguiPlugin {
+                enable_multipass_for_primary_context: true,
+            },
         ))
+        .add_plugins(GamePlugin)
         .add_systems(
             Startup,
             (setup_camera_and_lights, setup_level),
         )
         //#NOTE: We need to run setup_player under Update schedule, or it will spawn more than one player entity
-        .add_systems(Update, (setup_player).run_if(in_state(GameState::Loading)))
+        .add_systems(Update, (setup_player).run_if(in_state(GameState::Loading)));
+
+    app.run();
+
+}
+
+// Everything the game adds on top of the engine, physics and rendering plugins. Kept out of `main`
+// so that the tests can build the game's schedules without a window.
+struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_plugins((
+            WallPlugin,
+            CrouchPlugin,
+            ClimbPlugin,
+            LedgePlugin,
+            WaterPlugin,
+            KnockbackPlugin,
+            HealthPlugin,
+            MeleePlugin,
+            ComboPlugin,
+            ProjectilePlugin,
+            JuicePlugin,
+            DamagePlugin,
+            StatusPlugin,
+            GuardPlugin,
+            EnemyPlugin,
+        ))
+        .add_plugins((
+            BehaviorPlugin,
+            NavigationPlugin,
+            PerceptionPlugin,
+            FlyingPlugin,
+            EncounterPlugin,
+            BossPlugin,
+            StatsPlugin,
+            ProgressionPlugin,
+            SkillsPlugin,
+            ItemsPlugin,
+            ControlsPlugin,
+        ))
         .add_systems(
             FixedUpdate,
             (
@@ -79,12 +204,10 @@ fn main() {
                 animate_sprite,
                 face_player_to_camera,
                 // prepare_animations,
-                // handle_animating,
+                handle_animating,
             ).run_if(in_state(GameState::Ready))
         );
-
-    app.run();
-
+    }
 }
 
 // fn setup(
@@ -128,14 +251,17 @@ fn main() {
 
 fn animate_sprite(
     time: Res<Time>,
-    mut query: Query<(&mut AnimationTimer, &mut Sprite3d, &TnuaAnimatingState<AnimationState>)>,
+    // Sprites in hitstop keep their current frame.
+    mut query: Query<
+        (&mut AnimationTimer, &mut Sprite3d, &TnuaAnimatingState<AnimationState>),
+        Without<Hitstop>,
+    >,
 ) {
 
     for (mut timer, mut sprite_3d, state) in query.iter_mut() {
         //use match patter to implement different logic for different animation states
         match state.get() {
             Some(AnimationState::Running(_)) => {
-                println!("Running");
                 timer.tick(time.delta());
                 if timer.just_finished() {
                     let length = sprite_3d.texture_atlas_keys.as_ref().unwrap().len();
@@ -143,23 +269,32 @@ fn animate_sprite(
                     atlas.index = (atlas.index + 1) % length;
                 }
             }
-            Some(AnimationState::Standing) => {
-                println!("Standing");
-                // 重設為第0幀
-                let atlas = sprite_3d.texture_atlas.as_mut().unwrap();
-                atlas.index = 0;
-            }
-            Some(AnimationState::Jumping) => {
-                println!("Jumping");
+            Some(
+                AnimationState::Standing
+                | AnimationState::Jumping
+                | AnimationState::Falling
+                | AnimationState::WallSliding
+                | AnimationState::WallJumping
+                | AnimationState::Crouching
+                | AnimationState::Sliding
+                | AnimationState::Climbing
+                | AnimationState::LedgeHanging
+                | AnimationState::Mantling
+                | AnimationState::Swimming
+                | AnimationState::Dashing
+                | AnimationState::Flying
+                | AnimationState::Hurt
+                | AnimationState::Guarding
+                | AnimationState::Parrying
+            ) => {
                 // 重設為第0幀
                 let atlas = sprite_3d.texture_atlas.as_mut().unwrap();
                 atlas.index = 0;
             }
-            Some(AnimationState::Falling) => {
-                println!("Falling");
-                // 重設為第0幀
+            Some(AnimationState::Attacking(index)) => {
+                // 攻擊動畫的每一幀由攻擊資料決定
                 let atlas = sprite_3d.texture_atlas.as_mut().unwrap();
-                atlas.index = 0;
+                atlas.index = *index;
             }
             None => ()
         }
@@ -195,6 +330,7 @@ fn setup_camera_and_lights(mut commands: Commands) {
     commands.spawn((
         Camera3d::default(),
         Transform::from_xyz(0.0, 16.0, 40.0).looking_at(Vec3::new(0.0, 10.0, 0.0), Vec3::Y),
+        CameraShake::default(),
     ));
 
     commands.spawn((PointLight::default(), Transform::from_xyz(5.0, 5.0, 5.0)));
@@ -213,6 +349,7 @@ fn setup_camera_and_lights(mut commands: Commands) {
 // No Tnua-related setup here - this is just normal Bevy (and Avian) stuff.
 fn setup_level(
     mut commands: Commands,
+    asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
@@ -225,6 +362,164 @@ fn setup_level(
         RigidBody::Static,
         Collider::half_space(Vec3::Y),
     ));
+
+    // A platform to climb onto.
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(6.0, 1.0, 4.0))),
+        MeshMaterial3d(materials.add(Color::srgb(0.6, 0.6, 0.6))),
+        Transform::from_xyz(8.0, 4.0, 0.0),
+        RigidBody::Static,
+        Collider::cuboid(6.0, 1.0, 4.0),
+    ));
+
+    // A ladder leading up to the platform. It sticks out above the platform so that the player
+    // can still grab it while standing on top.
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(1.0, 6.0, 0.2))),
+        MeshMaterial3d(materials.add(Color::srgb(0.5, 0.3, 0.1))),
+        Transform::from_xyz(4.4, 3.0, -0.5),
+        Collider::cuboid(1.0, 6.0, 1.0),
+        Climbable::bundle(),
+    ));
+
+    // A pool of water, deep enough to swim in.
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(10.0, 5.0, 4.0))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgba(0.2, 0.4, 0.9, 0.4),
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        })),
+        Transform::from_xyz(-12.0, 2.5, 0.0),
+        Collider::cuboid(10.0, 5.0, 4.0),
+        Water::default().bundle(),
+    ));
+
+    // Spikes that hurt the player when touched.
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(2.0, 1.0, 4.0))),
+        MeshMaterial3d(materials.add(Color::srgb(0.8, 0.1, 0.1))),
+        Transform::from_xyz(15.0, 0.5, 0.0),
+        Collider::cuboid(2.0, 1.0, 4.0),
+        Damage {
+            amount: 20.0,
+            knockback: 6.0,
+            damage_type: default_damage_type(),
+            inflicts: vec!["poison".to_string()],
+            interval: 1.0,
+        }
+        .hazard_bundle(),
+    ));
+
+    // A checkpoint past the spikes.
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(0.2, 4.0, 0.2))),
+        MeshMaterial3d(materials.add(Color::srgb(0.9, 0.8, 0.1))),
+        Transform::from_xyz(20.0, 2.0, 0.0),
+        Collider::cuboid(1.0, 4.0, 4.0),
+        Checkpoint::bundle(),
+    ));
+
+    // A training dummy to try attacks on.
+    let dummy = commands
+        .spawn((
+            Mesh3d(meshes.add(Cuboid::new(1.0, 2.0, 1.0))),
+            MeshMaterial3d(materials.add(Color::srgb(0.7, 0.5, 0.3))),
+            Transform::from_xyz(-4.0, 1.0, 0.0),
+            RigidBody::Static,
+            Collider::cuboid(1.0, 2.0, 1.0),
+            Health::new(50.0),
+            Loot(vec![LootDrop {
+                item: "straw".to_string(),
+                count: 3,
+            }]),
+            // Straw burns well, but shrugs off blows.
+            Resistances(
+                [("fire".to_string(), -0.5), ("physical".to_string(), 0.25)]
+                    .into_iter()
+                    .collect(),
+            ),
+            StatusEffects::default(),
+        ))
+        .id();
+    commands.spawn((
+        Hurtbox::bundle(dummy, Collider::cuboid(1.0, 2.0, 1.0)),
+        ChildOf(dummy),
+    ));
+
+    // Enemies: one guarding the platform (turning around at its edges), and one patrolling past
+    // the checkpoint.
+    let grunt = asset_server.load("enemies/grunt.enemy.ron");
+    commands.spawn((
+        Enemy { def: grunt.clone() },
+        Transform::from_xyz(9.0, 6.5, 0.0),
+    ));
+    commands.spawn((
+        Enemy { def: grunt },
+        PatrolRoute(vec![Vec3::new(24.0, 2.0, 0.0), Vec3::new(32.0, 2.0, 0.0)]),
+        Transform::from_xyz(28.0, 2.0, 0.0),
+    ));
+    // A tougher one, driven by a behavior tree.
+    commands.spawn((
+        Enemy {
+            def: asset_server.load("enemies/brute.enemy.ron"),
+        },
+        Transform::from_xyz(40.0, 2.5, 0.0),
+    ));
+
+    // Flyers: a bat waving about above the spikes, and a drone on its rounds over the brute.
+    commands.spawn((
+        Enemy {
+            def: asset_server.load("enemies/bat.enemy.ron"),
+        },
+        Transform::from_xyz(15.0, 5.0, 0.0),
+    ));
+    commands.spawn((
+        Enemy {
+            def: asset_server.load("enemies/drone.enemy.ron"),
+        },
+        Transform::from_xyz(40.0, 8.0, 0.0),
+    ));
+
+    // The boss waits past the pool, at the other end of the level.
+    commands.spawn((
+        Enemy {
+            def: asset_server.load("enemies/warden.enemy.ron"),
+        },
+        Boss::bundle(asset_server.load("bosses/warden.boss.ron")),
+        Transform::from_xyz(-32.0, 3.0, 0.0),
+    ));
+
+    // An arena at the end of the level: walking in shuts its gates until every wave is beaten.
+    let arena = commands
+        .spawn((
+            Transform::from_xyz(57.0, 3.0, 0.0),
+            Collider::cuboid(12.0, 6.0, 4.0),
+            Arena::bundle(
+                asset_server.load("encounters/pit.encounter.ron"),
+                Transform::from_xyz(57.0, 9.0, 26.0).looking_at(Vec3::new(57.0, 4.0, 0.0), Vec3::Y),
+            ),
+        ))
+        .id();
+    for x in [50.0, 64.0] {
+        commands.spawn((
+            Mesh3d(meshes.add(Cuboid::new(1.0, 8.0, 4.0))),
+            MeshMaterial3d(materials.add(Color::srgb(0.4, 0.2, 0.2))),
+            Transform::from_xyz(x, 4.0, 0.0),
+            Collider::cuboid(1.0, 8.0, 4.0),
+            ArenaGate::bundle(arena),
+        ));
+    }
+    for (id, position) in [
+        ("pit_left", Vec3::new(52.0, 2.5, 0.0)),
+        ("pit_right", Vec3::new(62.0, 2.5, 0.0)),
+        ("pit_top", Vec3::new(57.0, 8.0, 0.0)),
+    ] {
+        commands.spawn((
+            Spawner { id: id.to_string() },
+            Transform::from_translation(position),
+        ));
+    }
 }
 
 fn setup_player(mut commands: Commands, 
@@ -244,10 +539,11 @@ fn setup_player(mut commands: Commands,
         layout: assets.layout.clone(),
         index: 3,
     };
+    let swimmer = Swimmer::default();
 
     // -------------------- Spawn a 3D atlas sprite --------------------------
     println!("spawn_player");
-    commands.spawn((
+    let player = commands.spawn((
         Transform::from_xyz(0.0, 2.0, 0.0),
         // We'll need this in the `handle_animating` system to keep track of the players animating
         // state.
@@ -273,7 +569,74 @@ fn setup_player(mut commands: Commands,
             ..default()
         }.bundle_with_atlas(&mut sprite_params, texture_atlas))
     .insert(AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)))
-    .insert(Player);
+    .insert(Player)
+    .insert((
+        // The other abilities are unlocked in the skill tree.
+        MovementAbilities {
+            ledge_grab: true,
+            ..default()
+        },
+        // Counts the air jumps and dashes, so that each can only be done once per jump.
+        TnuaSimpleAirActionsCounter::default(),
+        WallMovement::default(),
+        WallState::default(),
+    ))
+    .insert((
+        CrouchMovement::default(),
+        CrouchState::default(),
+        // The enforcer casts its sensor from a bit above the character to detect low ceilings.
+        TnuaCrouchEnforcer::new(0.5 * Vec3::Y, |cmd| {
+            cmd.insert(TnuaAvian3dSensorShape(Collider::cylinder(0.5, 0.0)));
+        }),
+    ))
+    .insert((
+        ClimbMovement::default(),
+        ClimbState::default(),
+        // Climbing turns gravity off while the character is on the climbable.
+        GravityScale(1.0),
+    ))
+    .insert((LedgeGrab::default(), LedgeState::default()))
+    .insert((SwimState::new(&swimmer), swimmer))
+    .insert((Knockback::default(), HitStun::default()))
+    .insert((
+        Health::new(100.0),
+        Invulnerability::new(1.0),
+        Respawn::new(Vec3::new(0.0, 2.0, 0.0), 3),
+        StatusEffects::default(),
+    ))
+    .insert((
+        Facing(1.0),
+        MeleeAttacker {
+            combos: Some(asset_server.load("combos/player.combos.ron")),
+            ..MeleeAttacker::new(MeleeAttack::slash())
+        },
+        AttackState::default(),
+        ProjectileLauncher::new(ProjectileDef::default(), 0.3),
+        // Lets enemies hear the player jump, land and attack.
+        NoiseMaker::default(),
+    ))
+    .insert((
+        Guard::default(),
+        GuardState::default(),
+        Stamina::new(50.0, 20.0, 0.8),
+        DamageRoll::default(),
+    ))
+    .insert((
+        // The class sets the base stats for the player's level.
+        Stats::default(),
+        Experience::default(),
+        CharacterClass::new(asset_server.load("classes/adventurer.class.ron")),
+        SkillSet::new(asset_server.load("skills/player.skills.ron")),
+        UnlockedMoves::default(),
+        Inventory::new(24),
+    ))
+    .id();
+
+    // The player's hurtbox matches its body, and is a child so that it moves with the body.
+    commands.spawn((
+        Hurtbox::bundle(player, Collider::capsule(0.5, 1.0)),
+        ChildOf(player),
+    ));
 
 }
 
@@ -283,11 +646,29 @@ fn setup_player(mut commands: Commands,
 //
 // By itself this does not do much, but we can attach a `TnuaAnimatingState<AnimationState>`
 // component to the player entity and use it to track the animating state.
+#[derive(Deserialize, Clone, Debug)]
 pub enum AnimationState {
     Standing,
     Running(f32),
     Jumping,
     Falling,
+    WallSliding,
+    WallJumping,
+    Crouching,
+    Sliding,
+    Climbing,
+    LedgeHanging,
+    Mantling,
+    Swimming,
+    Dashing,
+    // Flying enemies don't stand, run or jump.
+    Flying,
+    Hurt,
+    Guarding,
+    // The first moments of the guard, while hits are parried.
+    Parrying,
+    // Shows the atlas index of the current attack frame.
+    Attacking(usize),
 }
 
 // Bevy's animation handling is a bit manual. We'll use this struct to register the animation clips
@@ -300,154 +681,242 @@ pub enum AnimationState {
 //     falling: AnimationNodeIndex,
 // }
 
-// This is the important system for this example
-// fn handle_animating(
-//     mut player_query: Query<(&TnuaController, &mut TnuaAnimatingState<AnimationState>)>,
-//     mut animation_player_query: Query<&mut AnimationPlayer>,
-//     animation_nodes: Option<Res<AnimationNodes>>,
-// ) {
-//     // An actual game should match the animation player and the controller. Here we cheat for
-//     // simplicity and use the only controller and only player.
-//     let Ok((controller, mut animating_state)) = player_query.single_mut() else {
-//         return;
-//     };
-//     let Ok(mut animation_player) = animation_player_query.single_mut() else {
-//         return;
-//     };
-//     let Some(animation_nodes) = animation_nodes else {
-//         return;
-//     };
+// This system projects the state of the player's controller (and of the extra movement modes) into
+// the `TnuaAnimatingState`, which `animate_sprite` then uses to decide which frames to show.
+fn handle_animating(
+    mut player_query: Query<
+        (
+            &TnuaController,
+            &mut TnuaAnimatingState<AnimationState>,
+            Option<&WallState>,
+            Option<&CrouchState>,
+            Option<&ClimbState>,
+            Option<&LedgeState>,
+            Option<&SwimState>,
+            Option<&HitStun>,
+            Option<&AttackState>,
+            Option<(&Guard, &GuardState)>,
+        ),
+        With<Player>,
+    >,
+) {
+    let Ok((
+        controller,
+        mut animating_state,
+        wall_state,
+        crouch_state,
+        climb_state,
+        ledge_state,
+        swim_state,
+        hit_stun,
+        attack_state,
+        guard,
+    )) = player_query.single_mut()
+    else {
+        return;
+    };
 
-//     // Here we use the data from TnuaController to determine what the character is currently doing,
-//     // so that we can later use that information to decide which animation to play.
-
-//     // First we look at the `action_name` to determine which action (if at all) the character is
-//     // currently performing:
-//     let current_status_for_animating = match controller.action_name() {
-//         // Unless you provide the action names yourself, prefer matching against the `NAME` const
-//         // of the `TnuaAction` trait. Once `type_name` is stabilized as `const` Tnua will use it to
-//         // generate these names automatically, which may result in a change to the name.
-//         Some(TnuaBuiltinJump::NAME) => {
-//             // In case of jump, we want to cast it so that we can get the concrete jump state.
-//             let (_, jump_state) = controller
-//                 .concrete_action::<TnuaBuiltinJump>()
-//                 .expect("action name mismatch");
-//             // Depending on the state of the jump, we need to decide if we want to play the jump
-//             // animation or the fall animation.
-//             match jump_state {
-//                 TnuaBuiltinJumpState::NoJump => return,
-//                 TnuaBuiltinJumpState::StartingJump { .. } => AnimationState::Jumping,
-//                 TnuaBuiltinJumpState::SlowDownTooFastSlopeJump { .. } => AnimationState::Jumping,
-//                 TnuaBuiltinJumpState::MaintainingJump { .. } => AnimationState::Jumping,
-//                 TnuaBuiltinJumpState::StoppedMaintainingJump => AnimationState::Jumping,
-//                 TnuaBuiltinJumpState::FallSection => AnimationState::Falling,
-//             }
-//         }
-//         // Tnua should only have the `action_name` of the actions you feed to it. If it has
-//         // anything else - consider it a bug.
-//         Some(other) => panic!("Unknown action {other}"),
-//         // No action name means that no action is currently being performed - which means the
-//         // animation should be decided by the basis.
-//         None => {
-//             // If there is no action going on, we'll base the animation on the state of the
-//             // basis.
-//             let Some((_, basis_state)) = controller.concrete_basis::<TnuaBuiltinWalk>() else {
-//                 // Since we only use the walk basis in this example, if we can't get get this
-//                 // basis' state it probably means the system ran before any basis was set, so we
-//                 // just stkip this frame.
-//                 return;
-//             };
-//             if basis_state.standing_on_entity().is_none() {
-//                 // The walk basis keeps track of what the character is standing on. If it doesn't
-//                 // stand on anything, `standing_on_entity` will be empty - which means the
-//                 // character has walked off a cliff and needs to fall.
-//                 AnimationState::Falling
-//             } else {
-//                 let speed = basis_state.running_velocity.length();
-//                 if 0.01 < speed {
-//                     AnimationState::Running(0.1 * speed)
-//                 } else {
-//                     AnimationState::Standing
-//                 }
-//             }
-//         }
-//     };
+    if hit_stun.is_some_and(HitStun::is_stunned) {
+        animating_state.update_by_discriminant(AnimationState::Hurt);
+        return;
+    }
+    if let Some((guard, guard_state)) = guard.filter(|(_, guard_state)| guard_state.guarding) {
+        animating_state.update_by_discriminant(if guard_state.is_parrying(guard) {
+            AnimationState::Parrying
+        } else {
+            AnimationState::Guarding
+        });
+        return;
+    }
+    if let Some(active) = attack_state.and_then(|attack| attack.active.as_ref()) {
+        animating_state
+            .update_by_discriminant(AnimationState::Attacking(active.current_frame().atlas_index));
+        return;
+    }
 
-//     let animating_directive = animating_state.update_by_discriminant(current_status_for_animating);
-
-//     match animating_directive {
-//         TnuaAnimatingStateDirective::Maintain { state } => {
-//             // `Maintain` means that we did not switch to a different variant, so there is no need
-//             // to change animations.
-
-//             // Specifically for the running animation, even when the state remains the speed can
-//             // still change. When it does, we simply need to update the speed in the animation
-//             // player.
-//             if let AnimationState::Running(speed) = state {
-//                 if let Some(animation) = animation_player.animation_mut(animation_nodes.running) {
-//                     animation.set_speed(*speed);
-//                 }
-//             }
-//         }
-//         TnuaAnimatingStateDirective::Alter {
-//             old_state: _,
-//             state,
-//         } => {
-//             // `Alter` means that we have switched to a different variant and need to play a
-//             // different animation.
-
-//             // First - stop the currently running animation. We don't check which one is running
-//             // here because we just assume it belongs to the old state, but more sophisticated code
-//             // can try to phase from the old animation to the new one.
-//             animation_player.stop_all();
-
-//             // Depending on the new state, we choose the animation to run and its parameters (here
-//             // they are the speed and whether or not to repeat)
-//             match state {
-//                 AnimationState::Standing => {
-//                     animation_player
-//                         .start(animation_nodes.standing)
-//                         .set_speed(1.0)
-//                         .repeat();
-//                 }
-//                 AnimationState::Running(speed) => {
-//                     animation_player
-//                         .start(animation_nodes.running)
-//                         // The running animation, in particular, has a speed that depends on how
-//                         // fast the character is running. Note that if the speed changes while the
-//                         // character is still running we won't get `Alter` again - so it's
-//                         // important to also update the speed in `Maintain { State: Running }`.
-//                         .set_speed(*speed)
-//                         .repeat();
-//                 }
-//                 AnimationState::Jumping => {
-//                     animation_player
-//                         .start(animation_nodes.jumping)
-//                         .set_speed(2.0);
-//                 }
-//                 AnimationState::Falling => {
-//                     animation_player
-//                         .start(animation_nodes.falling)
-//                         .set_speed(1.0);
-//                 }
-//             }
-//         }
-//     }
-// }
+    match ledge_state.map(|ledge| ledge.mode) {
+        Some(LedgeMode::Hanging { .. }) => {
+            animating_state.update_by_discriminant(AnimationState::LedgeHanging);
+            return;
+        }
+        Some(LedgeMode::Mantling { .. }) => {
+            animating_state.update_by_discriminant(AnimationState::Mantling);
+            return;
+        }
+        _ => {}
+    }
+
+    if climb_state.is_some_and(|climb| climb.climbing.is_some()) {
+        animating_state.update_by_discriminant(AnimationState::Climbing);
+        return;
+    }
+    if swim_state.is_some_and(SwimState::is_swimming) {
+        animating_state.update_by_discriminant(AnimationState::Swimming);
+        return;
+    }
+
+    // Movement modes that are implemented outside of Tnua's builtins take precedence, since Tnua
+    // itself is not aware of them.
+    let current_status_for_animating = match wall_state.map(|wall| wall.mode) {
+        Some(WallMode::Sliding) => AnimationState::WallSliding,
+        Some(WallMode::Jumping { .. }) => AnimationState::WallJumping,
+        _ => {
+            // Here we use the data from TnuaController to determine what the character is
+            // currently doing. First we look at the `action_name` to determine which action (if at
+            // all) the character is currently performing:
+            match controller.action_name() {
+                // Unless you provide the action names yourself, prefer matching against the `NAME`
+                // const of the `TnuaAction` trait.
+                Some(TnuaBuiltinJump::NAME) => {
+                    // In case of jump, we want to cast it so that we can get the concrete jump
+                    // state.
+                    let (_, jump_state) = controller
+                        .concrete_action::<TnuaBuiltinJump>()
+                        .expect("action name mismatch");
+                    // Depending on the state of the jump, we need to decide if we want to play the
+                    // jump animation or the fall animation.
+                    match jump_state {
+                        TnuaBuiltinJumpState::NoJump => return,
+                        TnuaBuiltinJumpState::StartingJump { .. } => AnimationState::Jumping,
+                        TnuaBuiltinJumpState::SlowDownTooFastSlopeJump { .. } => {
+                            AnimationState::Jumping
+                        }
+                        TnuaBuiltinJumpState::MaintainingJump { .. } => AnimationState::Jumping,
+                        TnuaBuiltinJumpState::StoppedMaintainingJump => AnimationState::Jumping,
+                        TnuaBuiltinJumpState::FallSection => AnimationState::Falling,
+                    }
+                }
+                Some(TnuaBuiltinDash::NAME) => AnimationState::Dashing,
+                Some(TnuaBuiltinCrouch::NAME) => {
+                    if crouch_state.is_some_and(|crouch| crouch.slide.is_some()) {
+                        AnimationState::Sliding
+                    } else {
+                        AnimationState::Crouching
+                    }
+                }
+                // Tnua should only have the `action_name` of the actions you feed to it. An action
+                // without an animation of its own shows the character standing.
+                Some(other) => {
+                    warn_once!("No animation for action {other}");
+                    AnimationState::Standing
+                }
+                // No action name means that no action is currently being performed - which means
+                // the animation should be decided by the basis.
+                None => {
+                    let Some((_, basis_state)) = controller.concrete_basis::<TnuaBuiltinWalk>()
+                    else {
+                        // The basis is fed by `apply_controls`, so if it's not there yet we just
+                        // skip this frame.
+                        return;
+                    };
+                    if basis_state.standing_on_entity().is_none() {
+                        // The character has walked off a cliff and needs to fall.
+                        AnimationState::Falling
+                    } else {
+                        let speed = basis_state.running_velocity.length();
+                        if 0.01 < speed {
+                            AnimationState::Running(0.1 * speed)
+                        } else {
+                            AnimationState::Standing
+                        }
+                    }
+                }
+            }
+        }
+    };
 
-fn apply_controls(keyboard: Res<ButtonInput<KeyCode>>, mut query: Query<&mut TnuaController>) {
-    let Ok(mut controller) = query.single_mut() else {
+    // `animate_sprite` reads the state directly, so we don't need to act on the directive here.
+    animating_state.update_by_discriminant(current_status_for_animating);
+}
+
+fn apply_controls(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    presses: Res<KeyPresses>,
+    mut query: Query<
+        (
+            &mut TnuaController,
+            &mut TnuaCrouchEnforcer,
+            Option<(&MovementAbilities, &mut TnuaSimpleAirActionsCounter)>,
+            Option<&WallState>,
+            Option<(&CrouchMovement, &CrouchState)>,
+            Option<&ClimbState>,
+            Option<&LedgeState>,
+            Option<(&Swimmer, &SwimState)>,
+            Option<(&Knockback, &HitStun)>,
+            &mut Facing,
+            Option<&mut AttackState>,
+            Option<&mut ProjectileLauncher>,
+            Option<&StatusEffects>,
+            Option<&mut GuardState>,
+            Option<&DerivedStats>,
+        ),
+        With<Player>,
+    >,
+) {
+    let Ok((
+        mut controller,
+        mut crouch_enforcer,
+        mut air_actions,
+        wall_state,
+        crouch,
+        climb_state,
+        ledge_state,
+        swim,
+        knockback,
+        mut facing,
+        attack_state,
+        launcher,
+        status_effects,
+        mut guard_state,
+        derived_stats,
+    )) = query.single_mut()
+    else {
         return;
     };
 
+    // The counter has to see every frame of the controller to tell the air actions apart.
+    if let Some((_, air_actions_counter)) = air_actions.as_mut() {
+        air_actions_counter.update(&controller);
+    }
+
+    // The guard is only held while the controls are not overridden by something else, so it's
+    // lowered here and raised again further down.
+    if let Some(guard_state) = guard_state.as_deref_mut() {
+        guard_state.requested = false;
+    }
+    let guarding = guard_state.as_ref().is_some_and(|guard_state| guard_state.guarding);
+
+    // A stunned player has no control over the character. The basis is still fed, but with a low
+    // acceleration so that the knockback is not cancelled right away.
+    if let Some((knockback, _)) = knockback.filter(|(_, hit_stun)| hit_stun.is_stunned()) {
+        controller.basis(TnuaBuiltinWalk {
+            float_height: FLOAT_HEIGHT,
+            acceleration: knockback.stunned_acceleration,
+            air_acceleration: knockback.stunned_acceleration,
+            ..Default::default()
+        });
+        return;
+    }
+
+    // While climbing or hanging from a ledge the character is driven directly by the respective
+    // module, and a status stun (unlike the hit stun) simply freezes the player in place. We still
+    // need to feed a basis, but it should not try to move the character and no actions should be
+    // fed.
+    if status_effects.is_some_and(StatusEffects::is_stunned)
+        || climb_state.is_some_and(|climb| climb.climbing.is_some())
+        || ledge_state.is_some_and(LedgeState::is_active)
+    {
+        controller.basis(TnuaBuiltinWalk {
+            float_height: FLOAT_HEIGHT,
+            ..Default::default()
+        });
+        return;
+    }
+
     let mut direction = Vec3::ZERO;
 
-    // if keyboard.pressed(KeyCode::ArrowUp) {
-    //     direction -= Vec3::Z;
-    // }
-    // if keyboard.pressed(KeyCode::ArrowDown) {
-    //     direction += Vec3::Z;
-    // }
+    // Up and Down are not used for walking in a side-scroller - they are read by the climb module
+    // (and Down also crouches).
     if keyboard.pressed(KeyCode::ArrowLeft) {
         direction -= Vec3::X;
     }
@@ -455,30 +924,121 @@ fn apply_controls(keyboard: Res<ButtonInput<KeyCode>>, mut query: Query<&mut Tnu
         direction += Vec3::X;
     }
 
+    // Right after a wall jump the push away from the wall overrides the player's input - otherwise
+    // holding towards the wall would cancel the wall jump right away.
+    if let Some(WallState { mode: WallMode::Jumping { away, .. }, .. }) = wall_state {
+        direction = *away;
+    }
+    // Guarding plants the character in place, facing the way it was when the guard went up.
+    if guarding {
+        direction = Vec3::ZERO;
+    }
+    if direction.x != 0.0 {
+        facing.0 = direction.x.signum();
+    }
+
+    let walk_speed = derived_stats.map_or(WALK_SPEED, |stats| stats.walk_speed);
+    let mut desired_velocity = direction.normalize_or_zero() * walk_speed;
+    if let Some((crouch_movement, crouch_state)) = crouch {
+        if let Some(slide) = crouch_state.slide {
+            // A slide keeps the momentum of the run regardless of the input.
+            desired_velocity = slide;
+        } else if crouch_state.crouching {
+            desired_velocity *= crouch_movement.speed_multiplier;
+        }
+    }
+    let swimming = swim.is_some_and(|(_, swim_state)| swim_state.is_swimming());
+    if let Some((swimmer, _)) = swim.filter(|_| swimming) {
+        desired_velocity = direction.normalize_or_zero() * swimmer.swim_speed;
+    }
+    // Slows and hastes apply on top of every other movement mode.
+    if let Some(status_effects) = status_effects {
+        desired_velocity *= status_effects.speed_multiplier();
+    }
+
     // Feed the basis every frame. Even if the player doesn't move - just use `desired_velocity:
     // Vec3::ZERO`. `TnuaController` starts without a basis, which will make the character collider
     // just fall.
     controller.basis(TnuaBuiltinWalk {
         // The `desired_velocity` determines how the character will move.
-        desired_velocity: direction.normalize_or_zero() * 10.0,
+        desired_velocity,
         desired_forward: Dir3::new(direction).ok(),
         // The `float_height` must be greater (even if by little) from the distance between the
         // character's center and the lowest point of its collider.
-        float_height: 2.0,
+        float_height: FLOAT_HEIGHT,
         // `TnuaBuiltinWalk` has many other fields for customizing the movement - but they have
         // sensible defaults. Refer to the `TnuaBuiltinWalk`'s documentation to learn what they do.
         ..Default::default()
     });
 
+    // Swimming up and down is done by the water module, and there is nothing to jump off or crouch
+    // on in the water.
+    if swimming {
+        return;
+    }
+
+    // While guarding the character can't attack or jump.
+    if let Some(mut guard_state) = guard_state {
+        guard_state.requested = keyboard.pressed(KeyCode::KeyZ);
+        if guarding {
+            return;
+        }
+    }
+
+    // Attacks are not Tnua actions - they are only requested here. The combo module decides which
+    // move comes out, and the melee module carries it out.
+    if let Some(mut attack_state) = attack_state {
+        if presses.just_pressed(KeyCode::KeyX) {
+            attack_state.request(AttackInput::Light);
+        } else if presses.just_pressed(KeyCode::KeyC) {
+            attack_state.request(AttackInput::Heavy);
+        }
+    }
+    if let Some(mut launcher) = launcher
+        && keyboard.pressed(KeyCode::KeyV)
+    {
+        launcher.requested = true;
+    }
+
     // Feed the jump action every frame as long as the player holds the jump button. If the player
-    // stops holding the jump button, simply stop feeding the action.
-    if keyboard.pressed(KeyCode::Space) {
+    // stops holding the jump button, simply stop feeding the action. Jumping off walls is handled
+    // by the wall module, so the regular jump is not fed while interacting with a wall. Holding
+    // Shift dashes instead, once the dash is unlocked - the double jump and the air dash share a
+    // single air action.
+    let on_wall = wall_state.is_some_and(|wall| wall.mode != WallMode::None);
+    let abilities = air_actions
+        .as_ref()
+        .map_or(MovementAbilities::default(), |(abilities, _)| **abilities);
+    let air_count = |name: &str| {
+        air_actions
+            .as_ref()
+            .map_or(usize::MAX, |(_, air_actions_counter)| air_actions_counter.air_count_for(name))
+    };
+    if keyboard.pressed(KeyCode::ShiftLeft) && abilities.dash && !on_wall {
+        controller.action(TnuaBuiltinDash {
+            displacement: Vec3::X * facing.0 * DASH_DISTANCE,
+            allow_in_air: air_count(TnuaBuiltinDash::NAME) <= 1,
+            speed: DASH_SPEED,
+            ..Default::default()
+        });
+    } else if keyboard.pressed(KeyCode::Space) && !on_wall {
         controller.action(TnuaBuiltinJump {
             // The height is the only mandatory field of the jump button.
-            height: 4.0,
+            height: derived_stats.map_or(JUMP_HEIGHT, |stats| stats.jump_height),
+            allow_in_air: abilities.double_jump && air_count(TnuaBuiltinJump::NAME) <= 1,
             // `TnuaBuiltinJump` also has customization fields with sensible defaults.
             ..Default::default()
         });
+    } else if let Some((crouch_movement, _)) =
+        crouch.filter(|(_, crouch_state)| crouch_state.crouching)
+    {
+        // Crouching is an action too. Feeding it through the enforcer lets the enforcer keep it
+        // going when the player lets go of the key under a low ceiling.
+        controller.action(crouch_enforcer.enforcing(TnuaBuiltinCrouch {
+            // The crouch lowers the `float_height` of the walk basis by this offset.
+            float_offset: crouch_movement.float_offset,
+            ..Default::default()
+        }));
     }
 }
 
@@ -520,8 +1080,8 @@ fn face_player_to_camera(
     camera_query: Query<&Transform, (With<Camera3d>, Without<Player>)>,
     mut player_query: Query<&mut Transform, With<Player>>,
 ) {
-    let Ok(camera_transform) = camera_query.get_single() else { return; };
-    let Ok(mut player_transform) = player_query.get_single_mut() else { return; };
+    let Ok(camera_transform) = camera_query.single() else { return; };
+    let Ok(mut player_transform) = player_query.single_mut() else { return; };
 
     // 只考慮 XZ 平面上的朝向
     let player_pos = player_transform.translation;
@@ -532,4 +1092,60 @@ fn face_player_to_camera(
     if look_dir.length_squared() > 0.0001 {
         player_transform.look_at(camera_pos, Vec3::Y);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::ecs::schedule::ScheduleLabel;
+    use bevy::input::InputPlugin;
+    use bevy::render::mesh::MeshPlugin;
+    use bevy::scene::ScenePlugin;
+    use bevy::state::app::StatesPlugin;
+
+    // The game without a window or renderer, with the physics and Tnua plugins whose sets the
+    // game's systems are ordered against.
+    fn headless_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            InputPlugin,
+            StatesPlugin,
+        ))
+        .init_asset::<StandardMaterial>()
+        .init_asset::<Image>()
+        .init_state::<GameState>()
+        .add_plugins((
+            PhysicsPlugins::default(),
+            TnuaControllerPlugin::new(FixedUpdate),
+            TnuaAvian3dPlugin::new(FixedUpdate),
+        ))
+        .add_plugins(GamePlugin);
+        app
+    }
+
+    fn initialize(app: &mut App, label: impl ScheduleLabel) {
+        let result = app
+            .world_mut()
+            .schedule_scope(label, |world, schedule| schedule.initialize(world));
+        if let Err(error) = result {
+            panic!("{error}");
+        }
+    }
+
+    #[test]
+    fn schedules_build() {
+        let mut app = headless_app();
+        app.finish();
+        app.cleanup();
+        app.update();
+        // The first update doesn't step the fixed timestep, so its schedule is built by hand.
+        initialize(&mut app, FixedUpdate);
+        initialize(&mut app, FixedLast);
+    }
+}
@@ -0,0 +1,332 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+use serde::Deserialize;
+
+use crate::combo::ComboSet;
+use crate::damage::default_damage_type;
+use crate::health::DamageEvent;
+use crate::juice::Hitstop;
+use crate::knockback::HitStun;
+use crate::layers::GameLayer;
+use crate::{Facing, GameState};
+
+pub struct MeleePlugin;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MeleeSystems;
+
+impl Plugin for MeleePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (advance_attacks, detect_hits)
+                .chain()
+                .in_set(MeleeSystems)
+                .after(TnuaUserControlsSystemSet)
+                .run_if(in_state(GameState::Ready)),
+        );
+    }
+}
+
+// The part of an attack frame that can hurt. `offset` and `knockback` are for an attacker facing
+// right - they get mirrored when facing left.
+#[derive(Deserialize, Clone, Debug)]
+pub struct HitboxFrame {
+    pub offset: Vec2,
+    pub size: Vec2,
+    pub damage: f32,
+    #[serde(default = "default_damage_type")]
+    pub damage_type: String,
+    // The impulse pushing hit entities away. A strong upward component makes a launcher.
+    pub knockback: Vec2,
+}
+
+// A single frame of an attack's sprite animation.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AttackFrame {
+    pub atlas_index: usize,
+    pub duration: f32,
+    #[serde(default)]
+    pub hitbox: Option<HitboxFrame>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MeleeAttack {
+    pub frames: Vec<AttackFrame>,
+}
+
+impl MeleeAttack {
+    // A quick forward slash: wind-up, a single active frame, and recovery.
+    pub fn slash() -> Self {
+        Self {
+            frames: vec![
+                AttackFrame {
+                    atlas_index: 1,
+                    duration: 0.08,
+                    hitbox: None,
+                },
+                AttackFrame {
+                    atlas_index: 2,
+                    duration: 0.1,
+                    hitbox: Some(HitboxFrame {
+                        offset: Vec2::new(1.0, 0.2),
+                        size: Vec2::new(1.4, 1.2),
+                        damage: 10.0,
+                        damage_type: default_damage_type(),
+                        knockback: Vec2::new(3.6, 1.8),
+                    }),
+                },
+                AttackFrame {
+                    atlas_index: 3,
+                    duration: 0.15,
+                    hitbox: None,
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct MeleeAttacker {
+    // Used for light attacks when there is no combo set (or it's not loaded yet).
+    pub attack: MeleeAttack,
+    pub combos: Option<Handle<ComboSet>>,
+    // How long (in seconds) an attack input is remembered while it can't be acted upon yet.
+    pub buffer_time: f32,
+}
+
+impl MeleeAttacker {
+    pub fn new(attack: MeleeAttack) -> Self {
+        Self {
+            attack,
+            combos: None,
+            buffer_time: 0.25,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttackInput {
+    Light,
+    Heavy,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BufferedInput {
+    pub input: AttackInput,
+    // Seconds since the input was pressed.
+    pub age: f32,
+}
+
+#[derive(Component, Default, Debug)]
+pub struct AttackState {
+    // The latest attack input that was not acted upon yet.
+    pub buffer: Option<BufferedInput>,
+    pub active: Option<ActiveAttack>,
+    pending: Option<ActiveAttack>,
+}
+
+impl AttackState {
+    // Called by the input layer (or the AI) when it wants to attack. Which attack comes out (if at
+    // all) is decided by the combo module.
+    pub fn request(&mut self, input: AttackInput) {
+        self.buffer = Some(BufferedInput { input, age: 0.0 });
+    }
+
+    // Starts `attack` on the next tick, cancelling the current attack if there is one.
+    pub fn start(&mut self, name: Option<String>, attack: MeleeAttack) {
+        self.pending = Some(ActiveAttack {
+            name,
+            attack,
+            frame: 0,
+            elapsed: 0.0,
+            total_elapsed: 0.0,
+            hitbox: None,
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct ActiveAttack {
+    // The name of the combo move, if the attack is one.
+    pub name: Option<String>,
+    pub attack: MeleeAttack,
+    pub frame: usize,
+    // Seconds spent in the current frame.
+    pub elapsed: f32,
+    // Seconds since the attack started.
+    pub total_elapsed: f32,
+    hitbox: Option<Entity>,
+}
+
+impl ActiveAttack {
+    pub fn current_frame(&self) -> &AttackFrame {
+        &self.attack.frames[self.frame]
+    }
+}
+
+// A short-lived collider that damages the hurtboxes it touches.
+#[derive(Component, Debug)]
+pub struct Hitbox {
+    pub owner: Entity,
+    pub damage: f32,
+    pub damage_type: String,
+    // The impulse pushing hit entities away, in the direction the owner faces.
+    pub knockback: Vec3,
+    // Every entity is only hit once by the same hitbox, even if it has several hurtboxes.
+    pub already_hit: Vec<Entity>,
+}
+
+// The part of a character that can be hit. Usually a child of the character.
+#[derive(Component, Debug)]
+pub struct Hurtbox {
+    pub owner: Entity,
+}
+
+impl Hurtbox {
+    pub fn bundle(owner: Entity, collider: Collider) -> impl Bundle {
+        (
+            Hurtbox { owner },
+            collider,
+            Sensor,
+            CollisionLayers::new(GameLayer::Hurtbox, [GameLayer::Hitbox]),
+        )
+    }
+}
+
+fn advance_attacks(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut query: Query<
+        (
+            Entity,
+            &Transform,
+            &mut AttackState,
+            Option<&Facing>,
+            Option<&HitStun>,
+        ),
+        Without<Hitstop>,
+    >,
+    mut hitboxes_query: Query<&mut Transform, (With<Hitbox>, Without<AttackState>)>,
+) {
+    for (entity, transform, mut state, facing, hit_stun) in query.iter_mut() {
+        let direction = facing.map_or(1.0, |facing| facing.0);
+
+        // Getting hit interrupts the attack.
+        if hit_stun.is_some_and(HitStun::is_stunned) {
+            state.buffer = None;
+            state.pending = None;
+            if let Some(hitbox) = state.active.take().and_then(|active| active.hitbox) {
+                commands.entity(hitbox).despawn();
+            }
+            continue;
+        }
+
+        let mut frame_changed = false;
+        if let Some(next) = state.pending.take() {
+            if let Some(hitbox) = state.active.take().and_then(|active| active.hitbox) {
+                commands.entity(hitbox).despawn();
+            }
+            state.active = Some(next);
+            // Spawn the first frame's hitbox right away.
+            frame_changed = true;
+        } else if let Some(active) = state.active.as_mut() {
+            active.elapsed += time.delta_secs();
+            active.total_elapsed += time.delta_secs();
+            while active.frame < active.attack.frames.len()
+                && active.current_frame().duration <= active.elapsed
+            {
+                active.elapsed -= active.current_frame().duration;
+                active.frame += 1;
+                frame_changed = true;
+            }
+        }
+
+        let Some(active) = state.active.as_mut() else {
+            continue;
+        };
+        if frame_changed {
+            if let Some(hitbox) = active.hitbox.take() {
+                commands.entity(hitbox).despawn();
+            }
+            if active.attack.frames.len() <= active.frame {
+                state.active = None;
+                continue;
+            }
+            if let Some(hitbox) = &active.current_frame().hitbox {
+                let offset = Vec3::new(hitbox.offset.x * direction, hitbox.offset.y, 0.0);
+                active.hitbox = Some(
+                    commands
+                        .spawn((
+                            Hitbox {
+                                owner: entity,
+                                damage: hitbox.damage,
+                                damage_type: hitbox.damage_type.clone(),
+                                knockback: Vec3::new(
+                                    hitbox.knockback.x * direction,
+                                    hitbox.knockback.y,
+                                    0.0,
+                                ),
+                                already_hit: Vec::new(),
+                            },
+                            Collider::cuboid(hitbox.size.x, hitbox.size.y, 1.0),
+                            Sensor,
+                            CollisionEventsEnabled,
+                            CollisionLayers::new(GameLayer::Hitbox, [GameLayer::Hurtbox]),
+                            Transform::from_translation(transform.translation + offset),
+                        ))
+                        .id(),
+                );
+            }
+        } else if let Some(hitbox) = active.hitbox {
+            // The hitbox is not parented to the attacker, because the attacker's sprite is rotated
+            // to face the camera - so it needs to follow the attacker manually.
+            if let (Some(hitbox_frame), Ok(mut hitbox_transform)) = (
+                &active.current_frame().hitbox,
+                hitboxes_query.get_mut(hitbox),
+            ) {
+                hitbox_transform.translation = transform.translation
+                    + Vec3::new(
+                        hitbox_frame.offset.x * direction,
+                        hitbox_frame.offset.y,
+                        0.0,
+                    );
+            }
+        }
+    }
+}
+
+fn detect_hits(
+    mut collision_reader: EventReader<CollisionStarted>,
+    mut hitboxes_query: Query<&mut Hitbox>,
+    hurtboxes_query: Query<&Hurtbox>,
+    mut damage_writer: EventWriter<DamageEvent>,
+) {
+    for CollisionStarted(first, second) in collision_reader.read() {
+        // The event does not tell which of the two is the hitbox.
+        let (hitbox, hurtbox) = if hitboxes_query.contains(*first) {
+            (*first, *second)
+        } else {
+            (*second, *first)
+        };
+        let (Ok(mut hitbox), Ok(hurtbox)) =
+            (hitboxes_query.get_mut(hitbox), hurtboxes_query.get(hurtbox))
+        else {
+            continue;
+        };
+        if hurtbox.owner == hitbox.owner || hitbox.already_hit.contains(&hurtbox.owner) {
+            continue;
+        }
+        hitbox.already_hit.push(hurtbox.owner);
+        damage_writer.write(DamageEvent {
+            target: hurtbox.owner,
+            source: Some(hitbox.owner),
+            amount: hitbox.damage,
+            damage_type: hitbox.damage_type.clone(),
+            knockback: hitbox.knockback,
+            ignores_invulnerability: false,
+            inflicts: Vec::new(),
+        });
+    }
+}
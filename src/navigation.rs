@@ -0,0 +1,698 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+
+use crate::GameState;
+use crate::enemy::{EnemyBrain, EnemyThinkSystems};
+use crate::layers::GameLayer;
+
+pub struct NavigationPlugin;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NavigationSystems;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NavSettings>()
+            .init_resource::<NavGraph>()
+            .add_systems(OnEnter(GameState::Ready), request_nav_graph_build)
+            .add_systems(
+                FixedUpdate,
+                (build_nav_graph, follow_paths, drive_nav_agents)
+                    .chain()
+                    .in_set(NavigationSystems)
+                    // Enemies set their agents' targets while thinking.
+                    .after(EnemyThinkSystems)
+                    .before(TnuaUserControlsSystemSet)
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// What the characters using the graph can do, which decides which links are generated.
+#[derive(Resource, Clone, Debug)]
+pub struct NavSettings {
+    // The distance between the nodes along a surface.
+    pub spacing: f32,
+    pub max_jump_height: f32,
+    pub max_jump_distance: f32,
+    pub max_fall: f32,
+    // The free space a character needs above a surface to stand on it.
+    pub clearance: f32,
+    // The graph only covers this range, which keeps infinite colliders (like the ground's
+    // half-space) finite.
+    pub min_x: f32,
+    pub max_x: f32,
+    pub min_y: f32,
+    pub max_y: f32,
+}
+
+impl Default for NavSettings {
+    fn default() -> Self {
+        Self {
+            spacing: 1.0,
+            max_jump_height: 3.5,
+            max_jump_distance: 5.0,
+            max_fall: 20.0,
+            clearance: 2.0,
+            min_x: -64.0,
+            max_x: 64.0,
+            min_y: -64.0,
+            max_y: 64.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NavLinkKind {
+    Walk,
+    // Jumping this high.
+    Jump { height: f32 },
+    // Stepping off a ledge.
+    Fall,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NavLink {
+    pub to: usize,
+    pub kind: NavLinkKind,
+    pub cost: f32,
+}
+
+// A point on top of a surface that a character can stand on.
+#[derive(Debug, Clone)]
+pub struct NavNode {
+    pub position: Vec3,
+    // The collider the node is on.
+    pub surface: Entity,
+    pub links: Vec<NavLink>,
+}
+
+// A step of a path: the node to go to, and how to get there from the previous one.
+#[derive(Debug, Clone, Copy)]
+pub struct NavStep {
+    #[allow(dead_code, reason = "followers only need the position and kind")]
+    pub node: usize,
+    pub position: Vec3,
+    pub kind: NavLinkKind,
+}
+
+// The walk/jump/fall graph over the level's static colliders. Set `dirty` to have it rebuilt
+// (e.g. after the level changed).
+#[derive(Resource, Default, Debug)]
+pub struct NavGraph {
+    pub nodes: Vec<NavNode>,
+    pub dirty: bool,
+}
+
+impl NavGraph {
+    // The node a character standing (or floating) at `position` is at: the closest one that is not
+    // above it.
+    pub fn nearest_node(&self, position: Vec3) -> Option<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.position.y <= position.y + 0.5)
+            .min_by(|(_, a), (_, b)| {
+                let cost = |node: &NavNode| {
+                    (node.position.x - position.x).abs() + (position.y - node.position.y).abs()
+                };
+                cost(a).total_cmp(&cost(b))
+            })
+            .map(|(index, _)| index)
+    }
+
+    // A* from the node nearest to `from` to the node nearest to `to`. The path does not include
+    // the starting node.
+    pub fn find_path(&self, from: Vec3, to: Vec3) -> Option<Vec<NavStep>> {
+        let start = self.nearest_node(from)?;
+        let goal = self.nearest_node(to)?;
+        let goal_position = self.nodes[goal].position;
+        let heuristic = |node: usize| self.nodes[node].position.distance(goal_position);
+
+        let mut costs = vec![f32::INFINITY; self.nodes.len()];
+        let mut came_from: Vec<Option<(usize, NavLinkKind)>> = vec![None; self.nodes.len()];
+        let mut open = BinaryHeap::new();
+        costs[start] = 0.0;
+        open.push(OpenNode {
+            node: start,
+            estimate: heuristic(start),
+        });
+
+        while let Some(OpenNode { node, estimate }) = open.pop() {
+            if node == goal {
+                let mut path = Vec::new();
+                let mut current = goal;
+                while let Some((previous, kind)) = came_from[current] {
+                    path.push(NavStep {
+                        node: current,
+                        position: self.nodes[current].position,
+                        kind,
+                    });
+                    current = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            // A stale entry - the node was reached more cheaply since.
+            if costs[node] + heuristic(node) < estimate {
+                continue;
+            }
+            for link in &self.nodes[node].links {
+                let cost = costs[node] + link.cost;
+                if cost < costs[link.to] {
+                    costs[link.to] = cost;
+                    came_from[link.to] = Some((node, link.kind));
+                    open.push(OpenNode {
+                        node: link.to,
+                        estimate: cost + heuristic(link.to),
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+// An entry of the A* open set, ordered so that `BinaryHeap` pops the lowest estimate first.
+#[derive(Debug, PartialEq)]
+struct OpenNode {
+    node: usize,
+    estimate: f32,
+}
+
+impl Eq for OpenNode {}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Makes a character walk (and jump, and fall) along a path to `target`.
+#[derive(Component, Debug)]
+pub struct NavAgent {
+    pub target: Option<Vec3>,
+    pub speed: f32,
+    // Must match the character's walk basis.
+    pub float_height: f32,
+    // Seconds between path searches while the target is set.
+    pub repath_interval: f32,
+    pub path: Vec<NavStep>,
+    // What the path follower wants the character to do: walk in this horizontal direction (-1, 0
+    // or 1), and jump this high.
+    pub heading: f32,
+    pub jump: Option<f32>,
+    until_repath: f32,
+}
+
+impl NavAgent {
+    pub fn new(speed: f32, float_height: f32) -> Self {
+        Self {
+            target: None,
+            speed,
+            float_height,
+            repath_interval: 0.5,
+            path: Vec::new(),
+            heading: 0.0,
+            jump: None,
+            until_repath: 0.0,
+        }
+    }
+}
+
+fn request_nav_graph_build(mut graph: ResMut<NavGraph>) {
+    graph.dirty = true;
+}
+
+fn build_nav_graph(
+    mut graph: ResMut<NavGraph>,
+    settings: Res<NavSettings>,
+    spatial_query: SpatialQuery,
+    colliders_query: Query<(Entity, &RigidBody, &ColliderAabb), (With<Collider>, Without<Sensor>)>,
+) {
+    if !graph.dirty {
+        return;
+    }
+    graph.dirty = false;
+
+    let statics: HashSet<Entity> = colliders_query
+        .iter()
+        .filter(|(_, rigid_body, _)| rigid_body.is_static())
+        .map(|(entity, ..)| entity)
+        .collect();
+    let filter = SpatialQueryFilter::from_mask(GameLayer::Default);
+    let only_statics = |entity: Entity| statics.contains(&entity);
+
+    // Sample the top of every static collider. Each surface keeps the range of its nodes so that
+    // its edges can be found later.
+    let mut nodes = Vec::new();
+    let mut surfaces = Vec::new();
+    for (entity, _, aabb) in colliders_query
+        .iter()
+        .filter(|(entity, ..)| statics.contains(entity))
+    {
+        let min_x = aabb.min.x.max(settings.min_x);
+        let max_x = aabb.max.x.min(settings.max_x);
+        let min_y = aabb.min.y.max(settings.min_y);
+        let max_y = aabb.max.y.min(settings.max_y);
+        if max_x < min_x || max_y < min_y {
+            continue;
+        }
+        let first = nodes.len();
+        let count = ((max_x - min_x) / settings.spacing).floor() as usize + 1;
+        for index in 0..count {
+            let x = min_x + (index as f32 + 0.5) * (max_x - min_x) / count as f32;
+            // The top of the box isn't always the top of the collider: unbounded colliders have
+            // huge boxes, and slopes aren't as high everywhere. Look for the surface from above.
+            let above = Vec3::new(x, max_y + 0.1, 0.0);
+            let Some(hit) = spatial_query.cast_ray_predicate(
+                above,
+                Dir3::NEG_Y,
+                max_y - min_y + 0.1,
+                true,
+                &filter,
+                &|hit| hit == entity,
+            ) else {
+                continue;
+            };
+            let position = above - Vec3::Y * hit.distance;
+            // Skip the spots covered by something else.
+            let blocked = spatial_query
+                .cast_ray_predicate(
+                    position + Vec3::Y * 0.05,
+                    Dir3::Y,
+                    settings.clearance,
+                    true,
+                    &filter,
+                    &only_statics,
+                )
+                .is_some();
+            if !blocked {
+                nodes.push(NavNode {
+                    position,
+                    surface: entity,
+                    links: Vec::new(),
+                });
+            }
+        }
+        if first < nodes.len() {
+            surfaces.push(first..nodes.len());
+        }
+    }
+
+    // Walk between neighbours on the same surface.
+    for surface in &surfaces {
+        for index in surface.start..surface.end - 1 {
+            let next = index + 1;
+            let distance = nodes[index].position.distance(nodes[next].position);
+            // A gap means something was blocking the space between the two.
+            if settings.spacing * 1.5 < distance {
+                continue;
+            }
+            let link = |to| NavLink {
+                to,
+                kind: NavLinkKind::Walk,
+                cost: distance,
+            };
+            nodes[index].links.push(link(next));
+            nodes[next].links.push(link(index));
+        }
+    }
+
+    // Fall off the edges of the surfaces onto whatever is below.
+    for surface in &surfaces {
+        for (edge, outward) in [(surface.start, -1.0), (surface.end - 1, 1.0)] {
+            let start = nodes[edge].position + Vec3::new(outward * settings.spacing, 0.5, 0.0);
+            let Some(hit) = spatial_query.cast_ray_predicate(
+                start,
+                Dir3::NEG_Y,
+                settings.max_fall,
+                true,
+                &filter,
+                &only_statics,
+            ) else {
+                continue;
+            };
+            let landing = start - Vec3::Y * hit.distance;
+            let Some(to) = closest_node_on(&nodes, hit.entity, landing) else {
+                continue;
+            };
+            let cost = nodes[edge].position.distance(nodes[to].position);
+            nodes[edge].links.push(NavLink {
+                to,
+                kind: NavLinkKind::Fall,
+                cost,
+            });
+        }
+    }
+
+    // Jump between surfaces: from every node to the closest node of every other surface in reach,
+    // as long as the way up and across is clear.
+    for from_surface in &surfaces {
+        for to_surface in surfaces.iter().filter(|to| *to != from_surface) {
+            for from in from_surface.clone() {
+                let from_position = nodes[from].position;
+                let Some(to) = to_surface.clone().min_by(|a, b| {
+                    let distance = |node: &usize| nodes[*node].position.distance(from_position);
+                    distance(a).total_cmp(&distance(b))
+                }) else {
+                    continue;
+                };
+                let to_position = nodes[to].position;
+                let rise = to_position.y - from_position.y;
+                if settings.max_jump_height < rise
+                    || settings.max_jump_height < -rise
+                    || settings.max_jump_distance < (to_position.x - from_position.x).abs()
+                {
+                    continue;
+                }
+                let apex = Vec3::new(
+                    from_position.x,
+                    from_position.y.max(to_position.y) + 1.0,
+                    0.0,
+                );
+                let clear = |a: Vec3, b: Vec3| {
+                    let Ok(direction) = Dir3::new(b - a) else {
+                        return true;
+                    };
+                    spatial_query
+                        .cast_ray_predicate(
+                            a,
+                            direction,
+                            a.distance(b),
+                            true,
+                            &filter,
+                            &only_statics,
+                        )
+                        .is_none()
+                };
+                let lifted = from_position + Vec3::Y * 0.5;
+                if !clear(lifted, apex) || !clear(apex, to_position + Vec3::Y * 1.0) {
+                    continue;
+                }
+                nodes[from].links.push(NavLink {
+                    to,
+                    kind: NavLinkKind::Jump {
+                        height: rise.max(0.0) + 1.5,
+                    },
+                    cost: from_position.distance(to_position) * 1.5 + 1.0,
+                });
+            }
+        }
+    }
+
+    graph.nodes = nodes;
+}
+
+fn closest_node_on(nodes: &[NavNode], surface: Entity, position: Vec3) -> Option<usize> {
+    nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| node.surface == surface)
+        .min_by(|(_, a), (_, b)| {
+            (a.position.x - position.x)
+                .abs()
+                .total_cmp(&(b.position.x - position.x).abs())
+        })
+        .map(|(index, _)| index)
+}
+
+fn follow_paths(
+    time: Res<Time>,
+    graph: Res<NavGraph>,
+    mut query: Query<(&GlobalTransform, &mut NavAgent)>,
+) {
+    for (transform, mut agent) in query.iter_mut() {
+        agent.heading = 0.0;
+        agent.jump = None;
+        let Some(target) = agent.target else {
+            agent.path.clear();
+            continue;
+        };
+        let position = transform.translation();
+
+        agent.until_repath -= time.delta_secs();
+        if agent.until_repath <= 0.0 {
+            agent.until_repath = agent.repath_interval;
+            agent.path = graph.find_path(position, target).unwrap_or_default();
+        }
+
+        // Skip the steps that were reached: close enough horizontally, and standing at their
+        // height (rather than under them).
+        let feet = position.y - agent.float_height;
+        while let Some(step) = agent.path.first() {
+            let reached =
+                (step.position.x - position.x).abs() < 0.4 && (feet - step.position.y).abs() < 0.75;
+            if !reached {
+                break;
+            }
+            agent.path.remove(0);
+        }
+        let Some(step) = agent.path.first().copied() else {
+            continue;
+        };
+
+        let offset = step.position.x - position.x;
+        if 0.1 < offset.abs() {
+            agent.heading = offset.signum();
+        }
+        // The jump is held until the character is up there.
+        if let NavLinkKind::Jump { height } = step.kind
+            && feet < step.position.y + 0.5
+        {
+            agent.jump = Some(height);
+        }
+    }
+}
+
+// Characters that are not enemies (e.g. companions) are driven by their agent directly. Enemies
+// fold the agent's output into their own controls.
+fn drive_nav_agents(mut query: Query<(&NavAgent, &mut TnuaController), Without<EnemyBrain>>) {
+    for (agent, mut controller) in query.iter_mut() {
+        let direction = Vec3::X * agent.heading;
+        controller.basis(TnuaBuiltinWalk {
+            desired_velocity: direction * agent.speed,
+            desired_forward: Dir3::new(direction).ok(),
+            float_height: agent.float_height,
+            ..Default::default()
+        });
+        if let Some(height) = agent.jump {
+            controller.action(TnuaBuiltinJump {
+                height,
+                ..Default::default()
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::render::mesh::MeshPlugin;
+    use bevy::scene::ScenePlugin;
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+
+    // A graph of nodes on the same surface, linked as given.
+    fn graph(positions: &[Vec3], links: &[(usize, usize, NavLinkKind, f32)]) -> NavGraph {
+        let mut nodes: Vec<NavNode> = positions
+            .iter()
+            .map(|position| NavNode {
+                position: *position,
+                surface: Entity::PLACEHOLDER,
+                links: Vec::new(),
+            })
+            .collect();
+        for (from, to, kind, cost) in links {
+            nodes[*from].links.push(NavLink {
+                to: *to,
+                kind: *kind,
+                cost: *cost,
+            });
+        }
+        NavGraph {
+            nodes,
+            dirty: false,
+        }
+    }
+
+    fn nodes_of(path: &[NavStep]) -> Vec<usize> {
+        path.iter().map(|step| step.node).collect()
+    }
+
+    #[test]
+    fn paths_take_the_cheapest_route() {
+        let graph = graph(
+            &[
+                Vec3::ZERO,
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 0.0),
+            ],
+            &[
+                (0, 2, NavLinkKind::Walk, 10.0),
+                (0, 1, NavLinkKind::Walk, 1.0),
+                (1, 2, NavLinkKind::Walk, 1.0),
+            ],
+        );
+        let path = graph
+            .find_path(Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0))
+            .unwrap();
+        assert_eq!(nodes_of(&path), [1, 2]);
+    }
+
+    #[test]
+    fn paths_keep_how_each_step_is_made() {
+        let jump = NavLinkKind::Jump { height: 3.0 };
+        let graph = graph(
+            &[
+                Vec3::ZERO,
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(2.0, 2.0, 0.0),
+            ],
+            &[
+                (0, 1, NavLinkKind::Walk, 1.0),
+                (1, 2, jump, 4.0),
+                (2, 0, NavLinkKind::Fall, 3.0),
+            ],
+        );
+        let path = graph
+            .find_path(Vec3::new(0.0, 1.0, 0.0), Vec3::new(2.0, 3.0, 0.0))
+            .unwrap();
+        assert_eq!(nodes_of(&path), [1, 2]);
+        assert_eq!(path[1].kind, jump);
+        assert_eq!(path[1].position, Vec3::new(2.0, 2.0, 0.0));
+
+        let path = graph
+            .find_path(Vec3::new(2.0, 3.0, 0.0), Vec3::new(0.0, 1.0, 0.0))
+            .unwrap();
+        assert_eq!(nodes_of(&path), [0]);
+        assert_eq!(path[0].kind, NavLinkKind::Fall);
+    }
+
+    #[test]
+    fn unreachable_targets_have_no_path() {
+        let graph = graph(
+            &[Vec3::ZERO, Vec3::new(5.0, 0.0, 0.0)],
+            &[(1, 0, NavLinkKind::Walk, 5.0)],
+        );
+        assert!(
+            graph
+                .find_path(Vec3::ZERO, Vec3::new(5.0, 0.0, 0.0))
+                .is_none()
+        );
+        // Already there.
+        assert!(graph.find_path(Vec3::ZERO, Vec3::ZERO).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_nearest_node_is_not_above() {
+        let graph = graph(&[Vec3::ZERO, Vec3::new(0.0, 3.0, 0.0)], &[]);
+        assert_eq!(graph.nearest_node(Vec3::new(0.0, 1.0, 0.0)), Some(0));
+        assert_eq!(graph.nearest_node(Vec3::new(0.0, 4.0, 0.0)), Some(1));
+        assert_eq!(graph.nearest_node(Vec3::new(0.0, -2.0, 0.0)), None);
+    }
+
+    // The ground's half-space, with a platform (x 4 to 8, top at 2) over it.
+    fn build_level_graph() -> NavGraph {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            PhysicsPlugins::new(PostUpdate),
+        ))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            1.0 / 60.0,
+        )))
+        .insert_resource(NavSettings {
+            min_x: -8.0,
+            max_x: 16.0,
+            min_y: -8.0,
+            max_y: 16.0,
+            ..default()
+        })
+        .insert_resource(NavGraph {
+            nodes: Vec::new(),
+            dirty: true,
+        });
+        app.world_mut()
+            .spawn((RigidBody::Static, Collider::half_space(Vec3::Y)));
+        app.world_mut().spawn((
+            RigidBody::Static,
+            Collider::cuboid(4.0, 1.0, 4.0),
+            Transform::from_xyz(6.0, 1.5, 0.0),
+        ));
+        app.finish();
+        app.cleanup();
+        // Let the physics place the colliders.
+        for _ in 0..4 {
+            app.update();
+        }
+        app.world_mut()
+            .run_system_once(build_nav_graph)
+            .expect("the graph should build");
+        app.world_mut().remove_resource::<NavGraph>().unwrap()
+    }
+
+    #[test]
+    fn nodes_sit_on_top_of_the_surfaces() {
+        let graph = build_level_graph();
+        let (ground, platform): (Vec<_>, Vec<_>) =
+            graph.nodes.iter().partition(|node| node.position.y < 1.0);
+        assert!(!ground.is_empty());
+        assert!(!platform.is_empty());
+        // Not at the top of the half-space's (unbounded) box.
+        assert!(ground.iter().all(|node| node.position.y.abs() < 0.01));
+        assert!(platform.iter().all(|node| {
+            (node.position.y - 2.0).abs() < 0.01 && (4.0..=8.0).contains(&node.position.x)
+        }));
+        // There's no room to stand under the platform.
+        assert!(
+            ground
+                .iter()
+                .all(|node| !(4.1..7.9).contains(&node.position.x))
+        );
+        assert!(
+            graph
+                .nodes
+                .iter()
+                .all(|node| (-8.0..=16.0).contains(&node.position.x))
+        );
+    }
+
+    #[test]
+    fn the_platform_is_reached_from_the_ground() {
+        let graph = build_level_graph();
+        let standing = Vec3::new(-4.0, 1.0, 0.0);
+        let start = graph.nearest_node(standing).unwrap();
+        assert!(graph.nodes[start].position.y.abs() < 0.01);
+
+        let path = graph.find_path(standing, Vec3::new(6.0, 3.0, 0.0)).unwrap();
+        let last = path.last().unwrap();
+        assert!((last.position.y - 2.0).abs() < 0.01);
+        assert!(
+            path.iter()
+                .any(|step| matches!(step.kind, NavLinkKind::Jump { .. }))
+        );
+
+        // And back down.
+        let path = graph.find_path(Vec3::new(6.0, 3.0, 0.0), standing).unwrap();
+        assert!(path.iter().any(|step| step.kind != NavLinkKind::Walk));
+        assert!(path.last().unwrap().position.y.abs() < 0.01);
+    }
+}
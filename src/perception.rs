@@ -0,0 +1,265 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+use serde::Deserialize;
+
+use crate::health::{DamageDealtEvent, Health};
+use crate::layers::GameLayer;
+use crate::melee::AttackState;
+use crate::{Facing, GameState, Player};
+
+pub struct PerceptionPlugin;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PerceptionSystems;
+
+impl Plugin for PerceptionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<NoiseEvent>()
+            .add_event::<AlertedEvent>()
+            .add_systems(
+                FixedUpdate,
+                (make_noises, perceive)
+                    .chain()
+                    .in_set(PerceptionSystems)
+                    .before(TnuaUserControlsSystemSet)
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// How an AI character senses the player.
+#[derive(Component, Deserialize, Clone, Debug)]
+pub struct Perception {
+    pub sight_range: f32,
+    // Half the angle of the sight cone, in degrees, around the direction the character faces.
+    pub sight_angle: f32,
+    // How far away a noise of loudness 1 is heard.
+    pub hearing_radius: f32,
+    // Allies in this radius are alerted when the character notices the player.
+    pub alert_radius: f32,
+    // Seconds after the last stimulus until the player is forgotten.
+    pub memory_duration: f32,
+}
+
+// What made an AI character aware of the player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stimulus {
+    Sight,
+    Hearing,
+    // Getting hit.
+    Damage,
+    // Told by an ally.
+    Alert,
+}
+
+// What an AI character knows about the player - which is what the AI acts upon.
+#[derive(Component, Default, Debug)]
+pub struct PerceptionMemory {
+    pub sees_player: bool,
+    // Where the player was when last seen, heard, or reported.
+    pub last_known: Option<Vec3>,
+    pub stimulus: Option<Stimulus>,
+    // Seconds since the last stimulus.
+    pub since_stimulus: f32,
+}
+
+impl PerceptionMemory {
+    pub fn remember(&mut self, position: Vec3, stimulus: Stimulus) {
+        self.last_known = Some(position);
+        self.stimulus = Some(stimulus);
+        self.since_stimulus = 0.0;
+    }
+
+    pub fn forget(&mut self) {
+        self.last_known = None;
+        self.stimulus = None;
+    }
+
+    pub fn is_aware(&self) -> bool {
+        self.last_known.is_some()
+    }
+}
+
+// A sound that AI characters within `loudness` times their hearing radius hear.
+#[derive(Event, Debug, Clone)]
+pub struct NoiseEvent {
+    pub source: Entity,
+    pub position: Vec3,
+    pub loudness: f32,
+}
+
+// Sent when an AI character becomes aware of the player (e.g. to show a "!" above it).
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "the template doesn't show alerts yet")]
+pub struct AlertedEvent {
+    pub entity: Entity,
+    pub stimulus: Stimulus,
+}
+
+// Makes a character noisy: jumping, landing and attacking send `NoiseEvent`s.
+#[derive(Component, Debug)]
+pub struct NoiseMaker {
+    pub jump_loudness: f32,
+    pub land_loudness: f32,
+    pub attack_loudness: f32,
+    was_jumping: bool,
+    was_airborne: bool,
+    was_attacking: bool,
+}
+
+impl Default for NoiseMaker {
+    fn default() -> Self {
+        Self {
+            jump_loudness: 1.0,
+            land_loudness: 0.8,
+            attack_loudness: 1.2,
+            was_jumping: false,
+            was_airborne: false,
+            was_attacking: false,
+        }
+    }
+}
+
+fn make_noises(
+    mut query: Query<(
+        Entity,
+        &GlobalTransform,
+        &mut NoiseMaker,
+        &TnuaController,
+        Option<&AttackState>,
+    )>,
+    mut noise_writer: EventWriter<NoiseEvent>,
+) {
+    for (entity, transform, mut noise_maker, controller, attack_state) in query.iter_mut() {
+        let jumping = controller.action_name() == Some(TnuaBuiltinJump::NAME);
+        let airborne = controller
+            .concrete_basis::<TnuaBuiltinWalk>()
+            .is_some_and(|(_, basis_state)| basis_state.standing_on_entity().is_none());
+        let attacking = attack_state.is_some_and(|attack_state| attack_state.active.is_some());
+
+        let mut noise = |loudness: f32| {
+            noise_writer.write(NoiseEvent {
+                source: entity,
+                position: transform.translation(),
+                loudness,
+            });
+        };
+        if jumping && !noise_maker.was_jumping {
+            noise(noise_maker.jump_loudness);
+        }
+        if !airborne && noise_maker.was_airborne {
+            noise(noise_maker.land_loudness);
+        }
+        if attacking && !noise_maker.was_attacking {
+            noise(noise_maker.attack_loudness);
+        }
+        noise_maker.was_jumping = jumping;
+        noise_maker.was_airborne = airborne;
+        noise_maker.was_attacking = attacking;
+    }
+}
+
+fn perceive(
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+    player_query: Query<(Entity, &GlobalTransform), With<Player>>,
+    characters_query: Query<(), With<Health>>,
+    mut noise_reader: EventReader<NoiseEvent>,
+    mut dealt_reader: EventReader<DamageDealtEvent>,
+    mut alerted_writer: EventWriter<AlertedEvent>,
+    mut query: Query<(
+        Entity,
+        &GlobalTransform,
+        &Perception,
+        &mut PerceptionMemory,
+        Option<&Facing>,
+    )>,
+) {
+    let Ok((player, player_transform)) = player_query.single() else {
+        return;
+    };
+    let player_position = player_transform.translation();
+    let noises: Vec<&NoiseEvent> = noise_reader
+        .read()
+        .filter(|noise| noise.source == player)
+        .collect();
+    let hits: Vec<Entity> = dealt_reader
+        .read()
+        .filter(|event| event.source == Some(player))
+        .map(|event| event.target)
+        .collect();
+    // Level geometry only - characters don't block the sight.
+    let level_filter = SpatialQueryFilter::from_mask(GameLayer::Default);
+    let is_level = |entity: Entity| !characters_query.contains(entity);
+
+    // The characters that just noticed the player, and where they are.
+    let mut alerts = Vec::new();
+    for (entity, transform, perception, mut memory, facing) in query.iter_mut() {
+        let position = transform.translation();
+        let was_aware = memory.is_aware();
+        memory.since_stimulus += time.delta_secs();
+
+        // Sight: in range, within the cone, and nothing in between.
+        let to_player = player_position - position;
+        let distance = to_player.length();
+        let forward = Vec3::X * facing.map_or(1.0, |facing| facing.0);
+        memory.sees_player = distance <= perception.sight_range
+            && forward.angle_between(to_player).to_degrees() <= perception.sight_angle
+            && Dir3::new(to_player).is_ok_and(|direction| {
+                spatial_query
+                    .cast_ray_predicate(
+                        position,
+                        direction,
+                        distance,
+                        true,
+                        &level_filter,
+                        &is_level,
+                    )
+                    .is_none()
+            });
+        if memory.sees_player {
+            memory.remember(player_position, Stimulus::Sight);
+        }
+
+        // Hearing: noises are heard through walls.
+        if !memory.sees_player
+            && let Some(noise) = noises.iter().find(|noise| {
+                position.distance(noise.position) <= perception.hearing_radius * noise.loudness
+            })
+        {
+            memory.remember(noise.position, Stimulus::Hearing);
+        }
+
+        // Getting hit gives the attacker away, even from behind.
+        if hits.contains(&entity) {
+            memory.remember(player_position, Stimulus::Damage);
+        }
+
+        if perception.memory_duration < memory.since_stimulus {
+            memory.forget();
+        }
+        if let (false, Some(stimulus)) = (was_aware, memory.stimulus) {
+            alerted_writer.write(AlertedEvent { entity, stimulus });
+            alerts.push((entity, position, perception.alert_radius));
+        }
+    }
+
+    // Tell the allies around. Alerted allies don't pass the alert on, so it doesn't spread
+    // through the whole level.
+    for (alerter, alerter_position, radius) in alerts {
+        for (entity, transform, _, mut memory, _) in query.iter_mut() {
+            if entity == alerter
+                || memory.is_aware()
+                || radius < transform.translation().distance(alerter_position)
+            {
+                continue;
+            }
+            memory.remember(player_position, Stimulus::Alert);
+            alerted_writer.write(AlertedEvent {
+                entity,
+                stimulus: Stimulus::Alert,
+            });
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::GameState;
+use crate::health::{DamageSystems, DeathEvent, DeathSystems};
+use crate::ron_asset::RonAssetLoader;
+use crate::stats::{Stat, Stats, StatsSystems};
+
+pub struct ProgressionPlugin;
+
+impl Plugin for ProgressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<LevelCurve>()
+            .register_asset_loader(RonAssetLoader::<LevelCurve>::new(&["curve.ron"]))
+            .init_asset::<ClassDef>()
+            .register_asset_loader(RonAssetLoader::<ClassDef>::new(&["class.ron"]))
+            .init_resource::<LevelUpFeedback>()
+            .add_event::<GainXpEvent>()
+            .add_event::<LevelUpEvent>()
+            .add_systems(Startup, load_level_curve)
+            .add_systems(
+                FixedUpdate,
+                (
+                    // The dead are despawned in `DeathSystems`, so their rewards are read before.
+                    reward_kills.after(DamageSystems).before(DeathSystems),
+                    (gain_xp, apply_class_growth)
+                        .chain()
+                        .before(StatsSystems)
+                        .after(reward_kills),
+                )
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(
+                Update,
+                (play_level_up_feedback, tick_level_up_bursts).run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// How much XP it takes to reach each level, loaded from a `.curve.ron` file. All amounts are totals
+// since level 1, not the XP between two levels.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct LevelCurve {
+    pub max_level: u32,
+    pub formula: CurveFormula,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum CurveFormula {
+    // The XP to reach level 2, 3 and so on. Levels past the end of the table can't be reached.
+    Table(Vec<u32>),
+    // `base * (level - 1) ^ exponent`.
+    Power { base: f32, exponent: f32 },
+    // Level 2 takes `first` XP, and every level after it `growth` times as much as the one before.
+    Geometric { first: f32, growth: f32 },
+}
+
+impl LevelCurve {
+    // The highest level the curve can reach.
+    pub fn max_level(&self) -> u32 {
+        match &self.formula {
+            CurveFormula::Table(table) => self.max_level.min(table.len() as u32 + 1),
+            _ => self.max_level,
+        }
+        .max(1)
+    }
+
+    // The total XP needed to reach `level`, or `None` past the max level.
+    pub fn xp_for_level(&self, level: u32) -> Option<u32> {
+        if level <= 1 {
+            return Some(0);
+        }
+        if self.max_level() < level {
+            return None;
+        }
+        let steps = (level - 1) as f32;
+        let xp = match &self.formula {
+            CurveFormula::Table(table) => return table.get(level as usize - 2).copied(),
+            CurveFormula::Power { base, exponent } => base * steps.powf(*exponent),
+            CurveFormula::Geometric { first, growth } if (growth - 1.0).abs() < f32::EPSILON => {
+                first * steps
+            }
+            CurveFormula::Geometric { first, growth } => {
+                first * (growth.powf(steps) - 1.0) / (growth - 1.0)
+            }
+        };
+        Some(xp.round().max(0.0) as u32)
+    }
+
+    // The level reached with `xp` in total.
+    pub fn level_for_xp(&self, xp: u32) -> u32 {
+        let mut level = 1;
+        while self
+            .xp_for_level(level + 1)
+            .is_some_and(|needed| needed <= xp)
+        {
+            level += 1;
+        }
+        level
+    }
+}
+
+#[derive(Resource)]
+pub struct LevelCurveHandle(pub Handle<LevelCurve>);
+
+// A character class, loaded from a `.class.ron` file. A stat is its `base` value plus its `growth`
+// for every level past the first.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct ClassDef {
+    pub name: String,
+    pub base: HashMap<Stat, f32>,
+    #[serde(default)]
+    pub growth: HashMap<Stat, f32>,
+}
+
+impl ClassDef {
+    pub fn stat_at(&self, stat: Stat, level: u32) -> f32 {
+        let base = self.base.get(&stat).copied().unwrap_or(0.0);
+        let growth = self.growth.get(&stat).copied().unwrap_or(0.0);
+        base + growth * level.saturating_sub(1) as f32
+    }
+}
+
+// The class of a character. Sets the base values of its `Stats` for its level.
+#[derive(Component, Debug)]
+pub struct CharacterClass {
+    pub def: Handle<ClassDef>,
+    // The level the stats were last set for.
+    applied_level: Option<u32>,
+}
+
+impl CharacterClass {
+    pub fn new(def: Handle<ClassDef>) -> Self {
+        Self {
+            def,
+            applied_level: None,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Experience {
+    // The total XP gained.
+    pub xp: u32,
+    pub level: u32,
+}
+
+impl Default for Experience {
+    fn default() -> Self {
+        Self { xp: 0, level: 1 }
+    }
+}
+
+// The XP an enemy is worth to whoever kills it.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct XpReward(pub u32);
+
+// Gives XP to a character. Sent for kills, and by quests and anything else that rewards XP.
+#[derive(Event, Debug, Clone)]
+pub struct GainXpEvent {
+    pub entity: Entity,
+    pub amount: u32,
+}
+
+// Sent once for every level gained, so a big reward can send a few at once.
+#[derive(Event, Debug, Clone)]
+pub struct LevelUpEvent {
+    pub entity: Entity,
+    pub level: u32,
+}
+
+// What plays when a character levels up.
+#[derive(Resource, Clone, Debug)]
+pub struct LevelUpFeedback {
+    // The path of the sound to play, if any.
+    pub sound: Option<String>,
+    // The glowing sphere that grows out of the character.
+    pub burst_color: Color,
+    pub burst_radius: f32,
+    // In seconds.
+    pub burst_duration: f32,
+}
+
+impl Default for LevelUpFeedback {
+    fn default() -> Self {
+        Self {
+            sound: None,
+            burst_color: Color::srgba(1.0, 0.9, 0.4, 0.5),
+            burst_radius: 2.0,
+            burst_duration: 0.6,
+        }
+    }
+}
+
+fn load_level_curve(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(LevelCurveHandle(
+        asset_server.load("progression/xp.curve.ron"),
+    ));
+}
+
+fn reward_kills(
+    mut death_reader: EventReader<DeathEvent>,
+    rewards_query: Query<&XpReward>,
+    experience_query: Query<(), With<Experience>>,
+    mut xp_writer: EventWriter<GainXpEvent>,
+) {
+    for event in death_reader.read() {
+        let Some(killer) = event
+            .killer
+            .filter(|killer| experience_query.contains(*killer))
+        else {
+            continue;
+        };
+        if let Ok(reward) = rewards_query.get(event.entity) {
+            xp_writer.write(GainXpEvent {
+                entity: killer,
+                amount: reward.0,
+            });
+        }
+    }
+}
+
+fn gain_xp(
+    curve_handle: Res<LevelCurveHandle>,
+    curves: Res<Assets<LevelCurve>>,
+    mut xp_reader: EventReader<GainXpEvent>,
+    mut query: Query<&mut Experience>,
+    mut level_up_writer: EventWriter<LevelUpEvent>,
+) {
+    let Some(curve) = curves.get(&curve_handle.0) else {
+        return;
+    };
+    for event in xp_reader.read() {
+        let Ok(mut experience) = query.get_mut(event.entity) else {
+            continue;
+        };
+        experience.xp = experience.xp.saturating_add(event.amount);
+        let level = curve.level_for_xp(experience.xp);
+        while experience.level < level {
+            experience.level += 1;
+            level_up_writer.write(LevelUpEvent {
+                entity: event.entity,
+                level: experience.level,
+            });
+        }
+    }
+}
+
+fn apply_class_growth(
+    classes: Res<Assets<ClassDef>>,
+    mut query: Query<(&mut CharacterClass, &Experience, &mut Stats)>,
+) {
+    for (mut class, experience, mut stats) in query.iter_mut() {
+        if class.applied_level == Some(experience.level) {
+            continue;
+        }
+        let Some(def) = classes.get(&class.def) else {
+            continue;
+        };
+        for stat in Stat::ALL {
+            stats.set_base(stat, def.stat_at(stat, experience.level));
+        }
+        class.applied_level = Some(experience.level);
+        debug!("{} stats set for level {}", def.name, experience.level);
+    }
+}
+
+#[derive(Component, Debug)]
+struct LevelUpBurst {
+    elapsed: f32,
+    duration: f32,
+    radius: f32,
+}
+
+fn play_level_up_feedback(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    feedback: Res<LevelUpFeedback>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut level_up_reader: EventReader<LevelUpEvent>,
+) {
+    for event in level_up_reader.read() {
+        info!("{:?} reached level {}", event.entity, event.level);
+        if let Some(sound) = &feedback.sound {
+            commands.spawn((
+                AudioPlayer::new(asset_server.load(sound.clone())),
+                PlaybackSettings::DESPAWN,
+            ));
+        }
+        // A child, so that the burst follows the character.
+        commands.spawn((
+            Mesh3d(meshes.add(Sphere::new(1.0))),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: feedback.burst_color,
+                emissive: feedback.burst_color.to_linear() * 4.0,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })),
+            Transform::from_scale(Vec3::ZERO),
+            LevelUpBurst {
+                elapsed: 0.0,
+                duration: feedback.burst_duration,
+                radius: feedback.burst_radius,
+            },
+            ChildOf(event.entity),
+        ));
+    }
+}
+
+fn tick_level_up_bursts(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut LevelUpBurst, &mut Transform)>,
+) {
+    for (entity, mut burst, mut transform) in query.iter_mut() {
+        burst.elapsed += time.delta_secs();
+        if burst.duration <= burst.elapsed {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.scale = Vec3::splat(burst.radius * burst.elapsed / burst.duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(max_level: u32, formula: CurveFormula) -> LevelCurve {
+        LevelCurve { max_level, formula }
+    }
+
+    #[test]
+    fn table_curves_read_the_table() {
+        let curve = curve(10, CurveFormula::Table(vec![10, 30, 60]));
+        assert_eq!(curve.xp_for_level(2), Some(10));
+        assert_eq!(curve.xp_for_level(3), Some(30));
+        assert_eq!(curve.xp_for_level(4), Some(60));
+        assert_eq!(curve.level_for_xp(29), 2);
+        assert_eq!(curve.level_for_xp(30), 3);
+    }
+
+    #[test]
+    fn short_tables_cap_the_level() {
+        let curve = curve(10, CurveFormula::Table(vec![10, 30, 60]));
+        assert_eq!(curve.max_level(), 4);
+        assert_eq!(curve.xp_for_level(5), None);
+        assert_eq!(curve.level_for_xp(u32::MAX), 4);
+    }
+
+    #[test]
+    fn levels_zero_and_one_need_no_xp() {
+        // Neither reads the table (`level - 2` would underflow), even when it's empty.
+        for formula in [
+            CurveFormula::Table(vec![10, 30]),
+            CurveFormula::Table(Vec::new()),
+            CurveFormula::Power {
+                base: 100.0,
+                exponent: 2.0,
+            },
+        ] {
+            let curve = curve(10, formula);
+            assert_eq!(curve.xp_for_level(0), Some(0));
+            assert_eq!(curve.xp_for_level(1), Some(0));
+            assert_eq!(curve.level_for_xp(0), 1);
+        }
+        let empty = curve(10, CurveFormula::Table(Vec::new()));
+        assert_eq!(empty.max_level(), 1);
+        assert_eq!(empty.xp_for_level(2), None);
+        assert_eq!(empty.level_for_xp(1000), 1);
+    }
+
+    #[test]
+    fn power_curves() {
+        let curve = curve(
+            5,
+            CurveFormula::Power {
+                base: 100.0,
+                exponent: 2.0,
+            },
+        );
+        assert_eq!(curve.xp_for_level(2), Some(100));
+        assert_eq!(curve.xp_for_level(3), Some(400));
+        assert_eq!(curve.xp_for_level(5), Some(1600));
+        assert_eq!(curve.level_for_xp(399), 2);
+        assert_eq!(curve.level_for_xp(400), 3);
+    }
+
+    #[test]
+    fn geometric_curves() {
+        let curve = curve(
+            20,
+            CurveFormula::Geometric {
+                first: 50.0,
+                growth: 1.5,
+            },
+        );
+        // 50, then 75 more, then 112.5 more.
+        assert_eq!(curve.xp_for_level(2), Some(50));
+        assert_eq!(curve.xp_for_level(3), Some(125));
+        assert_eq!(curve.xp_for_level(4), Some(238));
+    }
+
+    #[test]
+    fn geometric_curves_without_growth_are_linear() {
+        let curve = curve(
+            20,
+            CurveFormula::Geometric {
+                first: 40.0,
+                growth: 1.0,
+            },
+        );
+        assert_eq!(curve.xp_for_level(2), Some(40));
+        assert_eq!(curve.xp_for_level(3), Some(80));
+        assert_eq!(curve.xp_for_level(5), Some(160));
+        assert_eq!(curve.level_for_xp(159), 4);
+    }
+
+    #[test]
+    fn xp_past_the_cap_stays_at_the_max_level() {
+        let curve = curve(
+            5,
+            CurveFormula::Power {
+                base: 100.0,
+                exponent: 2.0,
+            },
+        );
+        assert_eq!(curve.xp_for_level(6), None);
+        assert_eq!(curve.level_for_xp(1600), 5);
+        assert_eq!(curve.level_for_xp(u32::MAX), 5);
+    }
+
+    #[test]
+    fn class_stats_grow_past_the_first_level() {
+        let class = ClassDef {
+            name: "test".to_string(),
+            base: HashMap::from([(Stat::Hp, 100.0), (Stat::Str, 10.0)]),
+            growth: HashMap::from([(Stat::Hp, 12.0)]),
+        };
+        assert_eq!(class.stat_at(Stat::Hp, 1), 100.0);
+        assert_eq!(class.stat_at(Stat::Hp, 3), 124.0);
+        // Level 0 is treated as level 1.
+        assert_eq!(class.stat_at(Stat::Hp, 0), 100.0);
+        assert_eq!(class.stat_at(Stat::Str, 5), 10.0);
+        assert_eq!(class.stat_at(Stat::Agi, 5), 0.0);
+    }
+}
@@ -0,0 +1,356 @@
+use avian3d::prelude::*;
+use bevy::asset::RenderAssetUsages;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_sprite3d::prelude::*;
+
+use crate::damage::default_damage_type;
+use crate::guard::{Guard, GuardState, ParryEvent};
+use crate::health::{DamageEvent, Health};
+use crate::layers::GameLayer;
+use crate::melee::Hurtbox;
+use crate::{Facing, GameState};
+
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ProjectileSpawnEvent>()
+            .init_resource::<ProjectilePool>()
+            .add_systems(Startup, create_projectile_image)
+            .add_systems(OnEnter(GameState::Ready), prewarm_projectile_pool)
+            .add_systems(
+                FixedUpdate,
+                (fire_launchers, spawn_projectiles, move_projectiles)
+                    .chain()
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+// How many projectiles are created up front. The pool grows on demand past that.
+const PREWARMED_PROJECTILES: usize = 32;
+
+// Describes a kind of projectile. Shared by everything that shoots it.
+#[derive(Clone, Debug)]
+pub struct ProjectileDef {
+    pub speed: f32,
+    // Multiplies the world's gravity. 0 for projectiles that fly straight.
+    pub gravity_scale: f32,
+    // Seconds until the projectile disappears on its own.
+    pub lifetime: f32,
+    pub damage: f32,
+    pub damage_type: String,
+    pub knockback: f32,
+    // How many additional targets the projectile passes through before it's spent.
+    pub pierce: u32,
+    // How fast (in radians per second) the projectile turns towards its target. 0 disables homing.
+    pub homing_turn_rate: f32,
+}
+
+impl Default for ProjectileDef {
+    fn default() -> Self {
+        Self {
+            speed: 20.0,
+            gravity_scale: 0.0,
+            lifetime: 2.0,
+            damage: 5.0,
+            damage_type: default_damage_type(),
+            knockback: 2.0,
+            pierce: 0,
+            homing_turn_rate: 0.0,
+        }
+    }
+}
+
+// Send this to fire a projectile. It's taken from the pool if one is available.
+#[derive(Event, Debug, Clone)]
+pub struct ProjectileSpawnEvent {
+    pub owner: Entity,
+    pub position: Vec3,
+    pub direction: Dir3,
+    pub def: ProjectileDef,
+    // The entity a homing projectile flies towards.
+    pub target: Option<Entity>,
+}
+
+#[derive(Component, Debug)]
+pub struct Projectile {
+    pub owner: Entity,
+    pub velocity: Vec3,
+    pub def: ProjectileDef,
+    pub target: Option<Entity>,
+    pub age: f32,
+    pub pierced: u32,
+    // Targets already damaged, so that piercing projectiles hit each target only once.
+    pub already_hit: Vec<Entity>,
+}
+
+// Projectile entities that are not in flight, ready to be reused.
+#[derive(Resource, Default)]
+pub struct ProjectilePool {
+    image: Handle<Image>,
+    free: Vec<Entity>,
+}
+
+// Lets a character shoot projectiles in the direction it faces.
+#[derive(Component, Clone, Debug)]
+pub struct ProjectileLauncher {
+    pub def: ProjectileDef,
+    // Where projectiles appear, relative to the character when facing right.
+    pub muzzle_offset: Vec2,
+    // Seconds between shots.
+    pub cooldown: f32,
+    pub remaining_cooldown: f32,
+    // Set by the input layer (or the AI) to shoot as soon as the cooldown allows.
+    pub requested: bool,
+}
+
+impl ProjectileLauncher {
+    pub fn new(def: ProjectileDef, cooldown: f32) -> Self {
+        Self {
+            def,
+            muzzle_offset: Vec2::new(0.8, 0.3),
+            cooldown,
+            remaining_cooldown: 0.0,
+            requested: false,
+        }
+    }
+}
+
+fn create_projectile_image(mut images: ResMut<Assets<Image>>, mut pool: ResMut<ProjectilePool>) {
+    // There is no projectile art yet, so use a small orange square.
+    pool.image = images.add(Image::new_fill(
+        Extent3d {
+            width: 4,
+            height: 4,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[255, 160, 40, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    ));
+}
+
+fn spawn_pooled_projectile(
+    commands: &mut Commands,
+    sprite_params: &mut Sprite3dParams,
+    image: Handle<Image>,
+) -> Entity {
+    commands
+        .spawn((
+            Sprite3dBuilder {
+                image,
+                pixels_per_metre: 16.,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }
+            .bundle(sprite_params),
+            Visibility::Hidden,
+        ))
+        .id()
+}
+
+fn prewarm_projectile_pool(
+    mut commands: Commands,
+    mut sprite_params: Sprite3dParams,
+    mut pool: ResMut<ProjectilePool>,
+) {
+    for _ in 0..PREWARMED_PROJECTILES {
+        let entity = spawn_pooled_projectile(&mut commands, &mut sprite_params, pool.image.clone());
+        pool.free.push(entity);
+    }
+}
+
+fn fire_launchers(
+    time: Res<Time>,
+    mut query: Query<(Entity, &Transform, &mut ProjectileLauncher, Option<&Facing>)>,
+    mut spawn_writer: EventWriter<ProjectileSpawnEvent>,
+) {
+    for (entity, transform, mut launcher, facing) in query.iter_mut() {
+        launcher.remaining_cooldown -= time.delta_secs();
+        if !std::mem::take(&mut launcher.requested) || 0.0 < launcher.remaining_cooldown {
+            continue;
+        }
+        launcher.remaining_cooldown = launcher.cooldown;
+        let direction = facing.map_or(1.0, |facing| facing.0);
+        spawn_writer.write(ProjectileSpawnEvent {
+            owner: entity,
+            position: transform.translation
+                + Vec3::new(
+                    launcher.muzzle_offset.x * direction,
+                    launcher.muzzle_offset.y,
+                    0.0,
+                ),
+            direction: if 0.0 < direction {
+                Dir3::X
+            } else {
+                Dir3::NEG_X
+            },
+            def: launcher.def.clone(),
+            target: None,
+        });
+    }
+}
+
+fn spawn_projectiles(
+    mut commands: Commands,
+    mut spawn_reader: EventReader<ProjectileSpawnEvent>,
+    mut sprite_params: Sprite3dParams,
+    mut pool: ResMut<ProjectilePool>,
+) {
+    for event in spawn_reader.read() {
+        let entity = match pool.free.pop() {
+            Some(entity) => entity,
+            None => spawn_pooled_projectile(&mut commands, &mut sprite_params, pool.image.clone()),
+        };
+        commands.entity(entity).insert((
+            Projectile {
+                owner: event.owner,
+                velocity: event.direction * event.def.speed,
+                def: event.def.clone(),
+                target: event.target,
+                age: 0.0,
+                pierced: 0,
+                already_hit: Vec::new(),
+            },
+            Transform::from_translation(event.position),
+            Visibility::Inherited,
+        ));
+    }
+}
+
+fn move_projectiles(
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    spatial_query: SpatialQuery,
+    mut commands: Commands,
+    mut pool: ResMut<ProjectilePool>,
+    mut query: Query<(Entity, &mut Projectile, &mut Transform)>,
+    targets_query: Query<&GlobalTransform, Without<Projectile>>,
+    characters_query: Query<(), With<Health>>,
+    hurtboxes_query: Query<&Hurtbox>,
+    guards_query: Query<(&Guard, &GuardState, &Facing)>,
+    mut damage_writer: EventWriter<DamageEvent>,
+    mut parry_writer: EventWriter<ParryEvent>,
+) {
+    let dt = time.delta_secs();
+    for (entity, mut projectile, mut transform) in query.iter_mut() {
+        projectile.age += dt;
+
+        // Homing projectiles turn towards their target, keeping their speed.
+        if 0.0 < projectile.def.homing_turn_rate
+            && let Some(target) = projectile
+                .target
+                .and_then(|target| targets_query.get(target).ok())
+        {
+            let speed = projectile.velocity.length();
+            let max_angle = projectile.def.homing_turn_rate * dt;
+            if let (Ok(current), Ok(desired)) = (
+                Dir3::new(projectile.velocity),
+                Dir3::new(target.translation() - transform.translation),
+            ) {
+                let angle = current.angle_between(*desired);
+                if 0.0 < angle {
+                    let turned = current.slerp(desired, (max_angle / angle).min(1.0));
+                    projectile.velocity = turned * speed;
+                }
+            }
+        }
+        let gravity_scale = projectile.def.gravity_scale;
+        projectile.velocity += gravity.0 * gravity_scale * dt;
+
+        let step = projectile.velocity * dt;
+        let mut spent = false;
+        let mut reflected = false;
+        if let Ok(direction) = Dir3::new(step) {
+            let origin = transform.translation;
+
+            // Characters are on the default layer too, but they should be hit through their
+            // hurtboxes - so they are skipped when looking for level geometry.
+            let level_hit = spatial_query.cast_ray_predicate(
+                origin,
+                direction,
+                step.length(),
+                true,
+                &SpatialQueryFilter::from_mask(GameLayer::Default),
+                &|entity| !characters_query.contains(entity),
+            );
+            spent = level_hit.is_some();
+            let reach = level_hit.map_or(step.length(), |hit| hit.distance);
+
+            let mut hurtbox_hits = spatial_query.ray_hits(
+                origin,
+                direction,
+                reach,
+                8,
+                true,
+                &SpatialQueryFilter::from_mask(GameLayer::Hurtbox),
+            );
+            hurtbox_hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+            for hit in hurtbox_hits {
+                let Ok(hurtbox) = hurtboxes_query.get(hit.entity) else {
+                    continue;
+                };
+                if hurtbox.owner == projectile.owner
+                    || projectile.already_hit.contains(&hurtbox.owner)
+                {
+                    continue;
+                }
+
+                // A parry sends the projectile back where it came from, now belonging to the
+                // parrying character (and homing in on the one who shot it).
+                if guards_query
+                    .get(hurtbox.owner)
+                    .is_ok_and(|(guard, guard_state, facing)| {
+                        guard_state.is_parrying(guard) && direction.x * facing.0 < 0.0
+                    })
+                {
+                    projectile.velocity = -projectile.velocity;
+                    projectile.target = Some(projectile.owner);
+                    projectile.owner = hurtbox.owner;
+                    projectile.already_hit.clear();
+                    projectile.age = 0.0;
+                    parry_writer.write(ParryEvent {
+                        defender: hurtbox.owner,
+                        attacker: None,
+                    });
+                    reflected = true;
+                    // Turned back before reaching the wall further along the ray.
+                    spent = false;
+                    break;
+                }
+
+                projectile.already_hit.push(hurtbox.owner);
+                damage_writer.write(DamageEvent {
+                    target: hurtbox.owner,
+                    source: Some(projectile.owner),
+                    amount: projectile.def.damage,
+                    damage_type: projectile.def.damage_type.clone(),
+                    knockback: direction.with_y(0.0).normalize_or_zero() * projectile.def.knockback,
+                    ignores_invulnerability: false,
+                    inflicts: Vec::new(),
+                });
+                if projectile.def.pierce <= projectile.pierced {
+                    spent = true;
+                    break;
+                }
+                projectile.pierced += 1;
+            }
+        }
+
+        if spent || projectile.def.lifetime <= projectile.age {
+            // Back to the pool.
+            commands
+                .entity(entity)
+                .remove::<Projectile>()
+                .insert(Visibility::Hidden);
+            pool.free.push(entity);
+        } else if !reflected {
+            transform.translation += step;
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use bevy::prelude::*;
+use bevy_egui::{EguiContextPass, EguiContexts, egui};
+use serde::Deserialize;
+
+use crate::abilities::MovementAbilities;
+use crate::combo::UnlockedMoves;
+use crate::progression::LevelUpEvent;
+use crate::ron_asset::RonAssetLoader;
+use crate::stats::{StatModifier, Stats, StatsSystems};
+use crate::{GameState, Player};
+
+pub struct SkillsPlugin;
+
+impl Plugin for SkillsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<SkillTree>()
+            .register_asset_loader(RonAssetLoader::<SkillTree>::new(&["skills.ron"]))
+            .init_resource::<SkillScreen>()
+            .add_event::<LearnSkillEvent>()
+            .add_event::<SkillLearnedEvent>()
+            .add_systems(
+                FixedUpdate,
+                (grant_skill_points, learn_skills)
+                    .chain()
+                    .before(StatsSystems)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(Update, toggle_skill_screen)
+            .add_systems(EguiContextPass, show_skill_screen);
+    }
+}
+
+// A skill tree, loaded from a `.skills.ron` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct SkillTree {
+    // The skill points gained with every level.
+    pub points_per_level: u32,
+    pub nodes: Vec<SkillNode>,
+}
+
+impl SkillTree {
+    pub fn get(&self, id: &str) -> Option<&SkillNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    // How many prerequisites deep the node is - the row the skill screen shows it on.
+    fn depth(&self, node: &SkillNode) -> usize {
+        // The length of the chain is capped, in case the data has a cycle.
+        fn depth_of(tree: &SkillTree, node: &SkillNode, limit: usize) -> usize {
+            if limit == 0 {
+                return 0;
+            }
+            node.requires
+                .iter()
+                .filter_map(|id| tree.get(id))
+                .map(|required| depth_of(tree, required, limit - 1) + 1)
+                .max()
+                .unwrap_or(0)
+        }
+        depth_of(self, node, self.nodes.len())
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SkillNode {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    // In skill points.
+    #[serde(default = "default_cost")]
+    pub cost: u32,
+    // The ids of the nodes that have to be learned first.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    pub unlocks: Vec<SkillUnlock>,
+}
+
+fn default_cost() -> u32 {
+    1
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum SkillUnlock {
+    Ability(Ability),
+    // A passive bonus, added to the `Stats` as coming from "skill:<id>".
+    Stat(StatModifier),
+    // Unlocks the combo moves that require this id.
+    Move(String),
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ability {
+    DoubleJump,
+    Dash,
+    WallJump,
+    LedgeGrab,
+}
+
+// The skills of a character.
+#[derive(Component, Debug)]
+pub struct SkillSet {
+    pub tree: Handle<SkillTree>,
+    // Unspent skill points.
+    pub points: u32,
+    // The ids of the learned nodes, in the order they were learned.
+    pub learned: Vec<String>,
+}
+
+impl SkillSet {
+    pub fn new(tree: Handle<SkillTree>) -> Self {
+        Self {
+            tree,
+            points: 0,
+            learned: Vec::new(),
+        }
+    }
+
+    pub fn has_learned(&self, id: &str) -> bool {
+        self.learned.iter().any(|learned| learned == id)
+    }
+
+    pub fn availability(&self, node: &SkillNode) -> SkillAvailability {
+        if self.has_learned(&node.id) {
+            SkillAvailability::Learned
+        } else if !node.requires.iter().all(|id| self.has_learned(id)) {
+            SkillAvailability::Locked
+        } else if self.points < node.cost {
+            SkillAvailability::TooExpensive
+        } else {
+            SkillAvailability::Available
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkillAvailability {
+    Learned,
+    // Some prerequisite is not learned yet.
+    Locked,
+    TooExpensive,
+    Available,
+}
+
+// Asks to spend skill points on a node. Ignored unless the node is available.
+#[derive(Event, Debug, Clone)]
+pub struct LearnSkillEvent {
+    pub entity: Entity,
+    pub skill: String,
+}
+
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "nothing celebrates a learned skill yet")]
+pub struct SkillLearnedEvent {
+    pub entity: Entity,
+    pub skill: String,
+}
+
+fn grant_skill_points(
+    trees: Res<Assets<SkillTree>>,
+    mut level_up_reader: EventReader<LevelUpEvent>,
+    mut query: Query<&mut SkillSet>,
+) {
+    for event in level_up_reader.read() {
+        let Ok(mut skill_set) = query.get_mut(event.entity) else {
+            continue;
+        };
+        let points = trees
+            .get(&skill_set.tree)
+            .map_or(1, |tree| tree.points_per_level);
+        skill_set.points += points;
+    }
+}
+
+fn learn_skills(
+    trees: Res<Assets<SkillTree>>,
+    mut learn_reader: EventReader<LearnSkillEvent>,
+    mut query: Query<(
+        &mut SkillSet,
+        Option<&mut MovementAbilities>,
+        Option<&mut Stats>,
+        Option<&mut UnlockedMoves>,
+    )>,
+    mut learned_writer: EventWriter<SkillLearnedEvent>,
+) {
+    for event in learn_reader.read() {
+        let Ok((mut skill_set, mut abilities, mut stats, mut moves)) = query.get_mut(event.entity)
+        else {
+            continue;
+        };
+        let Some(node) = trees
+            .get(&skill_set.tree)
+            .and_then(|tree| tree.get(&event.skill))
+        else {
+            continue;
+        };
+        if skill_set.availability(node) != SkillAvailability::Available {
+            continue;
+        }
+        skill_set.points -= node.cost;
+        skill_set.learned.push(node.id.clone());
+
+        for unlock in &node.unlocks {
+            match unlock {
+                SkillUnlock::Ability(ability) => {
+                    let Some(abilities) = abilities.as_deref_mut() else {
+                        continue;
+                    };
+                    match ability {
+                        Ability::DoubleJump => abilities.double_jump = true,
+                        Ability::Dash => abilities.dash = true,
+                        Ability::WallJump => abilities.wall_jump = true,
+                        Ability::LedgeGrab => abilities.ledge_grab = true,
+                    }
+                }
+                SkillUnlock::Stat(modifier) => {
+                    if let Some(stats) = stats.as_deref_mut() {
+                        stats.add_modifier(format!("skill:{}", node.id), *modifier);
+                    }
+                }
+                SkillUnlock::Move(id) => {
+                    if let Some(moves) = moves.as_deref_mut() {
+                        moves.0.insert(id.clone());
+                    }
+                }
+            }
+        }
+        learned_writer.write(SkillLearnedEvent {
+            entity: event.entity,
+            skill: node.id.clone(),
+        });
+    }
+}
+
+#[derive(Resource, Default)]
+struct SkillScreen {
+    open: bool,
+}
+
+fn toggle_skill_screen(keyboard: Res<ButtonInput<KeyCode>>, mut screen: ResMut<SkillScreen>) {
+    if keyboard.just_pressed(KeyCode::KeyK) {
+        screen.open = !screen.open;
+    }
+}
+
+// Shows the player's tree one row per depth, with a button to learn every node that's available.
+fn show_skill_screen(
+    mut contexts: EguiContexts,
+    mut screen: ResMut<SkillScreen>,
+    trees: Res<Assets<SkillTree>>,
+    query: Query<(Entity, &SkillSet), With<Player>>,
+    mut learn_writer: EventWriter<LearnSkillEvent>,
+) {
+    if !screen.open {
+        return;
+    }
+    let Ok((entity, skill_set)) = query.single() else {
+        return;
+    };
+    let Some(tree) = trees.get(&skill_set.tree) else {
+        return;
+    };
+    let rows = tree
+        .nodes
+        .iter()
+        .map(|node| tree.depth(node))
+        .max()
+        .unwrap_or(0);
+
+    egui::Window::new("Skills")
+        .open(&mut screen.open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!("Skill points: {}", skill_set.points));
+            ui.separator();
+            for row in 0..=rows {
+                ui.horizontal(|ui| {
+                    for node in tree.nodes.iter().filter(|node| tree.depth(node) == row) {
+                        let availability = skill_set.availability(node);
+                        let color = match availability {
+                            SkillAvailability::Learned => egui::Color32::GREEN,
+                            SkillAvailability::Available => egui::Color32::WHITE,
+                            SkillAvailability::TooExpensive => egui::Color32::YELLOW,
+                            SkillAvailability::Locked => egui::Color32::GRAY,
+                        };
+                        ui.group(|ui| {
+                            ui.vertical(|ui| {
+                                ui.label(egui::RichText::new(&node.name).strong().color(color));
+                                if !node.description.is_empty() {
+                                    ui.label(&node.description);
+                                }
+                                if availability == SkillAvailability::Learned {
+                                    ui.label("Learned");
+                                    return;
+                                }
+                                let button = ui.add_enabled(
+                                    availability == SkillAvailability::Available,
+                                    egui::Button::new(format!("Learn ({})", node.cost)),
+                                );
+                                if button.clicked() {
+                                    learn_writer.write(LearnSkillEvent {
+                                        entity,
+                                        skill: node.id.clone(),
+                                    });
+                                }
+                            });
+                        });
+                    }
+                });
+            }
+        });
+}
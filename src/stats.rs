@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+use serde::Deserialize;
+
+use crate::GameState;
+use crate::health::Health;
+use crate::status::StatusEffects;
+
+pub struct StatsPlugin;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StatsSystems;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StatFormulas>()
+            .add_event::<StatChangedEvent>()
+            .add_systems(
+                FixedUpdate,
+                (apply_status_modifiers, update_stats)
+                    .chain()
+                    .in_set(StatsSystems)
+                    .before(TnuaUserControlsSystemSet)
+                    .run_if(in_state(GameState::Ready)),
+            );
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stat {
+    // The max health.
+    Hp,
+    Mp,
+    // Scales the damage dealt.
+    Str,
+    // Scales the damage taken.
+    Def,
+    // Scales the walk speed and the jump height.
+    Agi,
+    Int,
+}
+
+impl Stat {
+    pub const ALL: [Stat; 6] = [
+        Stat::Hp,
+        Stat::Mp,
+        Stat::Str,
+        Stat::Def,
+        Stat::Agi,
+        Stat::Int,
+    ];
+}
+
+// How a modifier changes a stat. A stat is its base value plus all the additions, times all the
+// multipliers.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ModifierOp {
+    Add(f32),
+    Multiply(f32),
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct StatModifier {
+    pub stat: Stat,
+    pub op: ModifierOp,
+}
+
+// The stats of a character. Modifiers are grouped by their source (e.g. "equipment:weapon" or
+// "status:haste") so that everything a source added can be taken off again at once.
+#[derive(Component, Clone, Debug, Default)]
+pub struct Stats {
+    base: HashMap<Stat, f32>,
+    modifiers: Vec<(String, StatModifier)>,
+    // The values as of the last `StatChangedEvent`s.
+    reported: HashMap<Stat, f32>,
+}
+
+impl Stats {
+    pub fn base(&self, stat: Stat) -> f32 {
+        self.base.get(&stat).copied().unwrap_or(0.0)
+    }
+
+    pub fn set_base(&mut self, stat: Stat, value: f32) {
+        self.base.insert(stat, value);
+    }
+
+    // The value with every modifier applied.
+    pub fn get(&self, stat: Stat) -> f32 {
+        let (added, multiplier) = self
+            .modifiers
+            .iter()
+            .filter(|(_, modifier)| modifier.stat == stat)
+            .fold(
+                (0.0, 1.0),
+                |(added, multiplier), (_, modifier)| match modifier.op {
+                    ModifierOp::Add(amount) => (added + amount, multiplier),
+                    ModifierOp::Multiply(factor) => (added, multiplier * factor),
+                },
+            );
+        (self.base(stat) + added) * multiplier
+    }
+
+    pub fn add_modifier(&mut self, source: impl Into<String>, modifier: StatModifier) {
+        self.modifiers.push((source.into(), modifier));
+    }
+
+    #[allow(dead_code, reason = "nothing takes its modifiers back yet")]
+    pub fn remove_source(&mut self, source: &str) {
+        self.modifiers
+            .retain(|(modifier_source, _)| modifier_source != source);
+    }
+}
+
+// How stats turn into the numbers the rest of the game works with. At the `baseline` value a stat
+// has no effect, and every point above (or below) it adds (or takes away) the `per_*` amounts.
+#[derive(Resource, Clone, Debug)]
+pub struct StatFormulas {
+    pub baseline: f32,
+    pub walk_speed: f32,
+    pub walk_speed_per_agi: f32,
+    pub jump_height: f32,
+    pub jump_height_per_agi: f32,
+    // Added to the multiplier of the damage dealt.
+    pub damage_per_str: f32,
+    // Taken off the multiplier of the damage taken.
+    pub defense_per_def: f32,
+}
+
+impl Default for StatFormulas {
+    fn default() -> Self {
+        Self {
+            baseline: 10.0,
+            walk_speed: 10.0,
+            walk_speed_per_agi: 0.1,
+            jump_height: 4.0,
+            jump_height_per_agi: 0.05,
+            damage_per_str: 0.05,
+            defense_per_def: 0.03,
+        }
+    }
+}
+
+// What the stats of a character work out to. Kept up to date by `update_stats`.
+#[derive(Component, Clone, Debug)]
+pub struct DerivedStats {
+    pub walk_speed: f32,
+    pub jump_height: f32,
+    pub damage_multiplier: f32,
+    pub defense_multiplier: f32,
+}
+
+impl DerivedStats {
+    fn from_stats(stats: &Stats, formulas: &StatFormulas) -> Self {
+        let over = |stat: Stat| stats.get(stat) - formulas.baseline;
+        Self {
+            walk_speed: (formulas.walk_speed + over(Stat::Agi) * formulas.walk_speed_per_agi)
+                .max(0.0),
+            jump_height: (formulas.jump_height + over(Stat::Agi) * formulas.jump_height_per_agi)
+                .max(0.0),
+            damage_multiplier: (1.0 + over(Stat::Str) * formulas.damage_per_str).max(0.1),
+            defense_multiplier: (1.0 - over(Stat::Def) * formulas.defense_per_def).clamp(0.1, 2.0),
+        }
+    }
+}
+
+// Sent whenever the value of a stat changes, whatever the reason.
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "the template doesn't show stat changes")]
+pub struct StatChangedEvent {
+    pub entity: Entity,
+    pub stat: Stat,
+    pub old: f32,
+    pub new: f32,
+}
+
+// Status effects are a source of modifiers like any other, named after the effect.
+fn apply_status_modifiers(mut query: Query<(&mut Stats, &StatusEffects)>) {
+    for (mut stats, status_effects) in query.iter_mut() {
+        let wanted: Vec<(String, StatModifier)> = status_effects
+            .active
+            .iter()
+            .flat_map(|active| {
+                let source = format!("status:{}", active.def.id);
+                active
+                    .def
+                    .stat_modifiers
+                    .iter()
+                    .map(move |modifier| (source.clone(), *modifier))
+            })
+            .collect();
+        let current: Vec<&(String, StatModifier)> = stats
+            .modifiers
+            .iter()
+            .filter(|(source, _)| source.starts_with("status:"))
+            .collect();
+        // Only touch the stats when something changed, so that change detection stays quiet.
+        if current.len() == wanted.len() && current.iter().zip(&wanted).all(|(a, b)| *a == b) {
+            continue;
+        }
+        stats
+            .modifiers
+            .retain(|(source, _)| !source.starts_with("status:"));
+        stats.modifiers.extend(wanted);
+    }
+}
+
+fn update_stats(
+    mut commands: Commands,
+    formulas: Res<StatFormulas>,
+    mut query: Query<(Entity, &mut Stats, Option<&mut Health>), Changed<Stats>>,
+    mut changed_writer: EventWriter<StatChangedEvent>,
+) {
+    for (entity, mut stats, health) in query.iter_mut() {
+        let stats = stats.bypass_change_detection();
+        for stat in Stat::ALL {
+            let new = stats.get(stat);
+            // The first values of a character are not a change.
+            if let Some(old) = stats.reported.insert(stat, new).filter(|old| *old != new) {
+                changed_writer.write(StatChangedEvent {
+                    entity,
+                    stat,
+                    old,
+                    new,
+                });
+            }
+        }
+
+        // Raising the max health heals by as much, lowering it only caps the current health.
+        if let Some(mut health) = health {
+            let max = stats.get(Stat::Hp);
+            if 0.0 < max && max != health.max {
+                let gained = (max - health.max).max(0.0);
+                health.max = max;
+                health.current = (health.current + gained).min(max);
+            }
+        }
+        commands
+            .entity(entity)
+            .insert(DerivedStats::from_stats(stats, &formulas));
+    }
+}
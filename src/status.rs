@@ -0,0 +1,290 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::GameState;
+use crate::damage::default_damage_type;
+use crate::health::{DamageEvent, HealthSystems};
+use crate::juice::HitFlash;
+use crate::ron_asset::RonAssetLoader;
+use crate::stats::StatModifier;
+
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<StatusEffectLibrary>()
+            .register_asset_loader(RonAssetLoader::<StatusEffectLibrary>::new(&["status.ron"]))
+            .add_event::<ApplyStatusEvent>()
+            .add_systems(Startup, load_status_effects)
+            .add_systems(
+                FixedUpdate,
+                (apply_status_effects, tick_status_effects)
+                    .chain()
+                    // Effects inflicted by damage are applied in the same tick, and damage over time
+                    // is dealt in the next one.
+                    .after(HealthSystems)
+                    .run_if(in_state(GameState::Ready)),
+            )
+            .add_systems(Update, tint_status_effects);
+    }
+}
+
+// All the status effects of the game, loaded from a `.status.ron` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct StatusEffectLibrary {
+    pub effects: Vec<StatusEffectDef>,
+}
+
+impl StatusEffectLibrary {
+    pub fn get(&self, id: &str) -> Option<&StatusEffectDef> {
+        self.effects.iter().find(|effect| effect.id == id)
+    }
+}
+
+#[derive(Resource)]
+pub struct StatusEffectLibraryHandle(pub Handle<StatusEffectLibrary>);
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StatusEffectDef {
+    pub id: String,
+    // In seconds.
+    pub duration: f32,
+    #[serde(default)]
+    pub stacking: Stacking,
+    // Damage dealt every `tick_interval` seconds, per stack.
+    #[serde(default)]
+    pub tick_damage: f32,
+    #[serde(default = "default_tick_interval")]
+    pub tick_interval: f32,
+    #[serde(default = "default_damage_type")]
+    pub damage_type: String,
+    // Multiplies the movement speed. Several effects multiply together.
+    #[serde(default = "default_multiplier")]
+    pub speed_multiplier: f32,
+    // Stunned characters can't act at all.
+    #[serde(default)]
+    pub stun: bool,
+    // Applied to the afflicted entity's `Stats` for as long as the effect lasts (once, whatever
+    // the stacks).
+    #[serde(default)]
+    pub stat_modifiers: Vec<StatModifier>,
+    // Multiplies the color of the afflicted entity's material.
+    #[serde(default)]
+    pub tint: Option<[f32; 3]>,
+    // Entities whose `StatusImmunities` contain any of these tags are not affected.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_tick_interval() -> f32 {
+    1.0
+}
+
+fn default_multiplier() -> f32 {
+    1.0
+}
+
+// What happens when an effect is applied to an entity that already has it.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Stacking {
+    // Restart the duration.
+    #[default]
+    Refresh,
+    // Add a stack (up to `max`) and restart the duration.
+    Stack {
+        max: u32,
+    },
+    // Keep the existing effect as it is.
+    Ignore,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveStatusEffect {
+    pub def: StatusEffectDef,
+    pub remaining: f32,
+    pub stacks: u32,
+    until_tick: f32,
+}
+
+// The status effects an entity currently has. Entities without this component can't get any.
+#[derive(Component, Default, Debug)]
+pub struct StatusEffects {
+    pub active: Vec<ActiveStatusEffect>,
+}
+
+impl StatusEffects {
+    pub fn apply(&mut self, def: &StatusEffectDef) {
+        let Some(existing) = self
+            .active
+            .iter_mut()
+            .find(|active| active.def.id == def.id)
+        else {
+            self.active.push(ActiveStatusEffect {
+                def: def.clone(),
+                remaining: def.duration,
+                stacks: 1,
+                until_tick: def.tick_interval,
+            });
+            return;
+        };
+        match def.stacking {
+            Stacking::Refresh => existing.remaining = def.duration,
+            Stacking::Stack { max } => {
+                existing.stacks = (existing.stacks + 1).min(max);
+                existing.remaining = def.duration;
+            }
+            Stacking::Ignore => {}
+        }
+    }
+
+    pub fn speed_multiplier(&self) -> f32 {
+        self.active
+            .iter()
+            .map(|active| active.def.speed_multiplier)
+            .product()
+    }
+
+    pub fn is_stunned(&self) -> bool {
+        self.active.iter().any(|active| active.def.stun)
+    }
+
+    // The combined tint of all the effects, or `None` when no effect is tinting.
+    pub fn tint(&self) -> Option<Vec3> {
+        self.active
+            .iter()
+            .filter_map(|active| active.def.tint)
+            .map(Vec3::from_array)
+            .reduce(|a, b| a * b)
+    }
+}
+
+// Tags of status effects that can't affect this entity (e.g. "fire" for a fire elemental).
+#[derive(Component, Default, Debug)]
+pub struct StatusImmunities(pub Vec<String>);
+
+// Keeps the material the entity had before being tinted by a status effect.
+#[derive(Component, Debug)]
+pub struct StatusTint {
+    original: Handle<StandardMaterial>,
+    tint: Vec3,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct ApplyStatusEvent {
+    pub target: Entity,
+    pub effect: String,
+}
+
+fn load_status_effects(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(StatusEffectLibraryHandle(
+        asset_server.load("status/effects.status.ron"),
+    ));
+}
+
+fn apply_status_effects(
+    mut apply_reader: EventReader<ApplyStatusEvent>,
+    library_handle: Res<StatusEffectLibraryHandle>,
+    libraries: Res<Assets<StatusEffectLibrary>>,
+    mut query: Query<(&mut StatusEffects, Option<&StatusImmunities>)>,
+) {
+    let Some(library) = libraries.get(&library_handle.0) else {
+        return;
+    };
+    for event in apply_reader.read() {
+        let Ok((mut effects, immunities)) = query.get_mut(event.target) else {
+            continue;
+        };
+        let Some(def) = library.get(&event.effect) else {
+            warn!("Unknown status effect {}", event.effect);
+            continue;
+        };
+        let immune = immunities
+            .is_some_and(|immunities| def.tags.iter().any(|tag| immunities.0.contains(tag)));
+        if !immune {
+            effects.apply(def);
+        }
+    }
+}
+
+fn tick_status_effects(
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut StatusEffects)>,
+    mut damage_writer: EventWriter<DamageEvent>,
+) {
+    let dt = time.delta_secs();
+    for (entity, mut effects) in query.iter_mut() {
+        for active in effects.active.iter_mut() {
+            active.remaining -= dt;
+            if active.def.tick_damage <= 0.0 {
+                continue;
+            }
+            active.until_tick -= dt;
+            if active.until_tick <= 0.0 {
+                active.until_tick += active.def.tick_interval;
+                damage_writer.write(DamageEvent {
+                    target: entity,
+                    source: None,
+                    amount: active.def.tick_damage * active.stacks as f32,
+                    damage_type: active.def.damage_type.clone(),
+                    knockback: Vec3::ZERO,
+                    // Damage over time should neither be blocked by nor cause invulnerability.
+                    ignores_invulnerability: true,
+                    inflicts: Vec::new(),
+                });
+            }
+        }
+        effects.active.retain(|active| 0.0 < active.remaining);
+    }
+}
+
+// Tints the material of entities with tinting effects. Entities that are flashing after a hit are
+// left alone until the flash is over, since it swaps the material as well.
+fn tint_status_effects(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut query: Query<
+        (
+            Entity,
+            &StatusEffects,
+            &mut MeshMaterial3d<StandardMaterial>,
+            Option<&StatusTint>,
+        ),
+        Without<HitFlash>,
+    >,
+) {
+    for (entity, effects, mut material, status_tint) in query.iter_mut() {
+        let tint = effects.tint();
+        match (tint, status_tint) {
+            (None, None) => {}
+            (None, Some(status_tint)) => {
+                material.0 = status_tint.original.clone();
+                commands.entity(entity).remove::<StatusTint>();
+            }
+            (Some(tint), status_tint) => {
+                if status_tint.is_some_and(|status_tint| status_tint.tint == tint) {
+                    continue;
+                }
+                let original = status_tint.map_or_else(
+                    || material.0.clone(),
+                    |status_tint| status_tint.original.clone(),
+                );
+                // Sprites with the same image share their material, so the tint needs a copy.
+                let Some(mut tinted) = materials.get(&original).cloned() else {
+                    continue;
+                };
+                let color = tinted.base_color.to_linear();
+                tinted.base_color = LinearRgba::new(
+                    color.red * tint.x,
+                    color.green * tint.y,
+                    color.blue * tint.z,
+                    color.alpha,
+                )
+                .into();
+                material.0 = materials.add(tinted);
+                commands
+                    .entity(entity)
+                    .insert(StatusTint { original, tint });
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+
+use crate::abilities::MovementAbilities;
+use crate::climb::ClimbState;
+use crate::controls::KeyPresses;
+use crate::layers::GameLayer;
+use crate::ledge::LedgeState;
+use crate::{GameState, Player};
+
+pub struct WallPlugin;
+
+impl Plugin for WallPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (detect_walls, update_wall_movement)
+                .chain()
+                .before(TnuaUserControlsSystemSet)
+                .run_if(in_state(GameState::Ready)),
+        );
+    }
+}
+
+// Tuning for wall slides and wall jumps. Characters need this together with `WallState` to
+// interact with walls at all.
+#[derive(Component, Clone, Debug)]
+pub struct WallMovement {
+    // The shape cast to each side to find walls. It should be a bit thinner than the character's
+    // collider so that it does not hit the floor the character stands on.
+    pub sensor_shape: Collider,
+    // How far from the sensor shape a wall still counts as touched.
+    pub reach: f32,
+    // The fastest the character may fall while sliding down a wall.
+    pub max_slide_speed: f32,
+    pub jump_height: f32,
+    // The horizontal speed of the push away from the wall when wall jumping.
+    pub push_off_speed: f32,
+    // For how long (in seconds) the push away from the wall overrides the horizontal input.
+    pub push_off_duration: f32,
+}
+
+impl Default for WallMovement {
+    fn default() -> Self {
+        Self {
+            sensor_shape: Collider::capsule(0.45, 1.0),
+            reach: 0.1,
+            max_slide_speed: 3.0,
+            jump_height: 3.0,
+            push_off_speed: 8.0,
+            push_off_duration: 0.2,
+        }
+    }
+}
+
+#[derive(Component, Default, Debug)]
+pub struct WallState {
+    // The normal of the wall the character is currently touching, if any.
+    pub contact: Option<Dir3>,
+    pub mode: WallMode,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum WallMode {
+    #[default]
+    None,
+    Sliding,
+    // Pushed away from the wall in the `away` direction for the `remaining` seconds.
+    Jumping {
+        away: Vec3,
+        remaining: f32,
+    },
+}
+
+fn detect_walls(
+    spatial_query: SpatialQuery,
+    mut query: Query<(Entity, &Transform, &WallMovement, &mut WallState)>,
+) {
+    for (entity, transform, movement, mut state) in query.iter_mut() {
+        // Only solid geometry counts as a wall - not triggers like ladders.
+        let filter =
+            SpatialQueryFilter::from_excluded_entities([entity]).with_mask(GameLayer::Default);
+        let config = ShapeCastConfig::from_max_distance(movement.reach);
+
+        // This is a side-scroller, so walls can only be to the left or to the right. Floors,
+        // ceilings and steep slopes are filtered out by requiring a (mostly) horizontal normal.
+        state.contact = [Dir3::X, Dir3::NEG_X].into_iter().find_map(|direction| {
+            let hit = spatial_query.cast_shape(
+                &movement.sensor_shape,
+                transform.translation,
+                Quat::IDENTITY,
+                direction,
+                &config,
+                &filter,
+            )?;
+            let normal = Dir3::new(hit.normal1).ok()?;
+            (normal.y.abs() < 0.3).then_some(normal)
+        });
+    }
+}
+
+fn update_wall_movement(
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    presses: Res<KeyPresses>,
+    mut query: Query<
+        (
+            &TnuaController,
+            &MovementAbilities,
+            &WallMovement,
+            &mut WallState,
+            &mut LinearVelocity,
+            Option<&ClimbState>,
+            Option<&LedgeState>,
+        ),
+        With<Player>,
+    >,
+) {
+    let Ok((controller, abilities, movement, mut state, mut velocity, climb_state, ledge_state)) =
+        query.single_mut()
+    else {
+        return;
+    };
+
+    if let WallMode::Jumping { remaining, .. } = &mut state.mode {
+        *remaining -= time.delta_secs();
+        if 0.0 < *remaining {
+            return;
+        }
+        state.mode = WallMode::None;
+    }
+
+    // Wall movement only makes sense in the air - when standing the walls are just obstacles.
+    let airborne = controller
+        .concrete_basis::<TnuaBuiltinWalk>()
+        .is_some_and(|(_, basis_state)| basis_state.standing_on_entity().is_none())
+        && climb_state.is_none_or(|climb| climb.climbing.is_none())
+        && !ledge_state.is_some_and(LedgeState::is_active);
+    let Some(normal) = state.contact.filter(|_| abilities.wall_jump && airborne) else {
+        state.mode = WallMode::None;
+        return;
+    };
+
+    let mut input = 0.0;
+    if keyboard.pressed(KeyCode::ArrowLeft) {
+        input -= 1.0;
+    }
+    if keyboard.pressed(KeyCode::ArrowRight) {
+        input += 1.0;
+    }
+
+    if state.mode == WallMode::Sliding && presses.just_pressed(KeyCode::Space) {
+        let away = Vec3::new(normal.x, 0.0, 0.0).normalize_or_zero();
+        let takeoff_speed = (2.0 * gravity.0.length() * movement.jump_height).sqrt();
+        velocity.0 = away * movement.push_off_speed + Vec3::Y * takeoff_speed;
+        state.mode = WallMode::Jumping {
+            away,
+            remaining: movement.push_off_duration,
+        };
+    } else if velocity.y <= 0.0 && input * normal.x < 0.0 {
+        // Cling to the wall only while falling and pushing into it, so that the player can still
+        // let go by releasing the direction key.
+        velocity.y = velocity.y.max(-movement.max_slide_speed);
+        state.mode = WallMode::Sliding;
+    } else {
+        state.mode = WallMode::None;
+    }
+}
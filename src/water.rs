@@ -0,0 +1,258 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_tnua::prelude::*;
+
+use crate::damage::default_damage_type;
+use crate::health::DamageEvent;
+use crate::layers::GameLayer;
+use crate::{GameState, Player};
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SplashEvent>().add_systems(
+            FixedUpdate,
+            (detect_water, apply_swimming)
+                .chain()
+                .before(TnuaUserControlsSystemSet)
+                .run_if(in_state(GameState::Ready)),
+        );
+    }
+}
+
+// A volume of water (or any other fluid). The top of its collider is the surface.
+#[derive(Component, Clone, Debug)]
+pub struct Water {
+    // The upward acceleration applied to a fully submerged character.
+    pub buoyancy: f32,
+    // How quickly the fluid slows down characters moving through it.
+    pub drag: f32,
+}
+
+impl Default for Water {
+    fn default() -> Self {
+        Self {
+            buoyancy: 6.0,
+            drag: 2.0,
+        }
+    }
+}
+
+impl Water {
+    // Everything a fluid volume needs besides its transform and collider.
+    pub fn bundle(self) -> impl Bundle {
+        (
+            self,
+            Sensor,
+            CollisionLayers::new(GameLayer::Trigger, [GameLayer::Default]),
+        )
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct Swimmer {
+    pub swim_speed: f32,
+    // How hard (in m/s²) the character can swim up or down.
+    pub stroke_acceleration: f32,
+    // Replaces the character's `GravityScale` while it's in a fluid.
+    pub gravity_scale: f32,
+    // Half of the character's height - used to tell how deep it is in the fluid.
+    pub half_height: f32,
+    // How far above the character's center its head is. The character is submerged (and uses
+    // oxygen) once its head is below the surface.
+    pub head_offset: f32,
+    // How long (in seconds) the character can stay submerged.
+    pub max_oxygen: f32,
+    // How many seconds of oxygen the character regains each second outside the fluid.
+    pub oxygen_refill_rate: f32,
+    // Dealt every `drowning_interval` seconds while the character is out of oxygen.
+    pub drowning_damage: f32,
+    pub drowning_interval: f32,
+}
+
+impl Default for Swimmer {
+    fn default() -> Self {
+        Self {
+            swim_speed: 5.0,
+            stroke_acceleration: 12.0,
+            gravity_scale: 0.3,
+            half_height: 1.0,
+            head_offset: 0.7,
+            max_oxygen: 10.0,
+            oxygen_refill_rate: 5.0,
+            drowning_damage: 10.0,
+            drowning_interval: 1.0,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct SwimState {
+    // The fluid volume the character is in. `None` means the character is not swimming.
+    pub water: Option<Entity>,
+    // The height of the fluid's surface, while swimming.
+    pub surface: f32,
+    pub submerged: bool,
+    // Seconds of oxygen left.
+    pub oxygen: f32,
+    // Seconds until the next drowning damage, once out of oxygen.
+    pub until_drowning: f32,
+}
+
+impl SwimState {
+    pub fn new(swimmer: &Swimmer) -> Self {
+        Self {
+            water: None,
+            surface: 0.0,
+            submerged: false,
+            oxygen: swimmer.max_oxygen,
+            until_drowning: 0.0,
+        }
+    }
+
+    pub fn is_swimming(&self) -> bool {
+        self.water.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplashKind {
+    Enter,
+    Exit,
+}
+
+// Sent when a character enters or leaves a fluid volume, for effects and sounds to react to.
+#[derive(Event, Debug, Clone)]
+#[allow(dead_code, reason = "the template has no splash effects or sounds yet")]
+pub struct SplashEvent {
+    pub entity: Entity,
+    pub water: Entity,
+    pub position: Vec3,
+    pub kind: SplashKind,
+}
+
+fn detect_water(
+    spatial_query: SpatialQuery,
+    water_query: Query<&ColliderAabb, With<Water>>,
+    mut query: Query<(
+        Entity,
+        &Transform,
+        &Collider,
+        &Swimmer,
+        &mut SwimState,
+        &mut GravityScale,
+    )>,
+    mut splash_writer: EventWriter<SplashEvent>,
+) {
+    for (entity, transform, collider, swimmer, mut state, mut gravity_scale) in query.iter_mut() {
+        let filter =
+            SpatialQueryFilter::from_excluded_entities([entity]).with_mask(GameLayer::Trigger);
+        // Only count as swimming once the water is at least at the character's waist.
+        let water = spatial_query
+            .shape_intersections(collider, transform.translation, Quat::IDENTITY, &filter)
+            .into_iter()
+            .filter_map(|water| Some((water, water_query.get(water).ok()?.max.y)))
+            .find(|(_, surface)| transform.translation.y < *surface);
+
+        let previous = state.water;
+        state.water = water.map(|(water, _)| water);
+        if let Some((_, surface)) = water {
+            state.surface = surface;
+        }
+        state.submerged = water
+            .is_some_and(|(_, surface)| transform.translation.y + swimmer.head_offset < surface);
+
+        let splash = match (previous, state.water) {
+            (None, Some(water)) => {
+                gravity_scale.0 = swimmer.gravity_scale;
+                Some((water, SplashKind::Enter))
+            }
+            (Some(water), None) => {
+                gravity_scale.0 = 1.0;
+                Some((water, SplashKind::Exit))
+            }
+            _ => None,
+        };
+        if let Some((water, kind)) = splash {
+            splash_writer.write(SplashEvent {
+                entity,
+                water,
+                position: Vec3::new(
+                    transform.translation.x,
+                    state.surface,
+                    transform.translation.z,
+                ),
+                kind,
+            });
+        }
+    }
+}
+
+fn apply_swimming(
+    time: Res<Time>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    water_query: Query<&Water>,
+    mut query: Query<(
+        Entity,
+        &Transform,
+        &Swimmer,
+        &mut SwimState,
+        &mut LinearVelocity,
+        Has<Player>,
+    )>,
+    mut damage_writer: EventWriter<DamageEvent>,
+) {
+    let dt = time.delta_secs();
+    for (entity, transform, swimmer, mut state, mut velocity, is_player) in query.iter_mut() {
+        if state.submerged {
+            state.oxygen = (state.oxygen - dt).max(0.0);
+        } else {
+            state.oxygen = (state.oxygen + swimmer.oxygen_refill_rate * dt).min(swimmer.max_oxygen);
+        }
+
+        // Out of oxygen, the character starts drowning - right away, and then every interval.
+        if state.submerged && state.oxygen <= 0.0 {
+            state.until_drowning -= dt;
+            if state.until_drowning <= 0.0 {
+                state.until_drowning += swimmer.drowning_interval;
+                damage_writer.write(DamageEvent {
+                    target: entity,
+                    source: None,
+                    amount: swimmer.drowning_damage,
+                    damage_type: default_damage_type(),
+                    knockback: Vec3::ZERO,
+                    ignores_invulnerability: true,
+                    inflicts: Vec::new(),
+                });
+            }
+        } else {
+            state.until_drowning = 0.0;
+        }
+
+        let Some(water) = state.water.and_then(|water| water_query.get(water).ok()) else {
+            continue;
+        };
+
+        // Buoyancy grows with how much of the character is below the surface, which makes it
+        // bob at the surface instead of shooting out of the water.
+        let bottom = transform.translation.y - swimmer.half_height;
+        let submersion = ((state.surface - bottom) / (2.0 * swimmer.half_height)).clamp(0.0, 1.0);
+        velocity.y += water.buoyancy * submersion * dt;
+
+        // Horizontal swimming goes through the walk basis, but Tnua does not control the vertical
+        // axis in the air - so swimming up and down is done here.
+        if is_player {
+            let mut stroke = 0.0;
+            if keyboard.pressed(KeyCode::ArrowUp) || keyboard.pressed(KeyCode::Space) {
+                stroke += 1.0;
+            }
+            if keyboard.pressed(KeyCode::ArrowDown) {
+                stroke -= 1.0;
+            }
+            velocity.y += stroke * swimmer.stroke_acceleration * dt;
+        }
+
+        velocity.0 /= 1.0 + water.drag * dt;
+    }
+}